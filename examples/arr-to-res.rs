@@ -1,9 +1,11 @@
+#![allow(dead_code)]
+
 fn all<T, E>(results: Vec<Result<T, E>>) -> Result<Vec<T>, E> {
     let mut values = vec![];
     for r in results {
         values.push(r?);
     }
-    return Ok(values);
+    Ok(values)
 }
 
 fn main() {}
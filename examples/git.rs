@@ -1,4 +1,5 @@
-use std::format;
+#![allow(dead_code)]
+
 use std::io::{self, Write};
 use std::process::Command;
 use std::thread;
@@ -9,7 +10,7 @@ fn synchronous() {
         .arg("clone")
         .arg(repo)
         .output()
-        .expect(&format!("fail to clone {}", repo));
+        .unwrap_or_else(|_| panic!("fail to clone {}", repo));
 
     if !output.status.success() {
         io::stderr().write_all(&output.stderr).unwrap();
@@ -21,11 +22,11 @@ fn synchronous() {
 fn asynchronous() {
     let repo = "git@gitlab.com:jdmichaud/observable.git";
     let jh = thread::spawn(move || {
-        return Command::new("git")
+        Command::new("git")
             .arg("clone")
             .arg(repo)
             .output()
-            .expect(&format!("fail to clone {}", repo));
+            .unwrap_or_else(|_| panic!("fail to clone {}", repo))
     });
     println!("wait on thread");
     let output = jh.join().unwrap();
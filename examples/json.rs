@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 extern crate serde;
 
 use serde::{Deserialize, Serialize};
@@ -9,7 +11,7 @@ struct Package<'a> {
     dependencies: HashMap<&'a str, &'a str>,
 }
 
-fn load_package(pjson: &str) -> Package {
+fn load_package(pjson: &str) -> Package<'_> {
     serde_json::from_str(pjson).expect("package.json not properly formated")
 }
 
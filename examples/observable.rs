@@ -1,3 +1,5 @@
+#![allow(dead_code, clippy::type_complexity, clippy::needless_return)]
+
 use std::error::Error;
 use std::rc::Rc;
 
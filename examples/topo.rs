@@ -6,7 +6,7 @@ fn topo_sort<'a>(graph: &Graph<'a, 'a>) -> Vec<&'a str> {
     fn topo_sort_rec<'a>(graph: &Graph<'a, 'a>, package: &'a str, result: &mut Vec<&'a str>) {
         let dependencies = graph.get(package).unwrap();
         let unresolved_dependencies = dependencies
-            .into_iter()
+            .iter()
             .filter(|p| !result.contains(p))
             .collect::<Vec<&&str>>();
         for ud in unresolved_dependencies {
@@ -22,7 +22,7 @@ fn topo_sort<'a>(graph: &Graph<'a, 'a>) -> Vec<&'a str> {
         topo_sort_rec(graph, package, &mut result);
     }
 
-    return result;
+    result
 }
 
 fn main() {
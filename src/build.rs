@@ -0,0 +1,710 @@
+//! Patching, building and packaging of the projects in dependency order.
+use crate::command;
+use crate::config::{seconds, Entry, Link, Lockfile, Publish, Retries, Timeouts};
+use crate::ecosystem::{ecosystem, Ecosystem, Manifest};
+use crate::event::say;
+use crate::event::{self, Event, Step};
+use crate::graph::{critical_path, Graph};
+use crate::progress::{dump_failures, dump_package_paths, load_failures, load_package_paths};
+use crate::retry::with_retry;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+/// How the projects are built, from the configuration and the command line.
+pub(crate) struct BuildOptions<'a> {
+    pub keep_going: bool,
+    pub retry: &'a Retries,
+    pub timeouts: &'a Timeouts,
+    /// Stamp the versions with this run id if set
+    pub run_id: Option<&'a str>,
+    pub publish: Option<&'a Publish>,
+    pub lockfile: Lockfile,
+}
+
+// The abbreviated commit the project is checked out at, if it is a git
+// repository
+fn short_sha(folder: &str) -> Option<String> {
+    let output = command::run(
+        Command::new("git")
+            .current_dir(folder)
+            .args(["rev-parse", "--short", "HEAD"]),
+        None,
+    )
+    .ok()?;
+    let sha = output.stdout.trim();
+    // A numeric prerelease identifier cannot have leading zeros
+    if sha.starts_with('0') && sha.chars().all(|c| c.is_ascii_digit()) {
+        Some(format!("g{}", sha))
+    } else {
+        Some(sha.to_string())
+    }
+}
+
+fn build_and_package(
+    project: &Project,
+    dependencies: &[(String, String)],
+    versions: &[(String, String)],
+    options: &BuildOptions,
+    timings: &mut Timings,
+) -> Result<String> {
+    let folder = project.folder.as_str();
+    let ecosystem = project.ecosystem;
+    let (retry, timeouts) = (options.retry, options.timeouts);
+    // Patch dependencies
+    match options.publish {
+        Some(publish) if publish.link == Link::Registry => {
+            ecosystem.link_registry(folder, versions, &publish.registry)?
+        }
+        _ => ecosystem.patch(folder, dependencies)?,
+    }
+    let locked = match options.lockfile {
+        Lockfile::Preserve => ecosystem.update_lockfile(folder, dependencies)?,
+        Lockfile::Discard => false,
+    };
+    if let Some(run_id) = options.run_id {
+        ecosystem.pin_versions(folder, versions)?;
+        let stamp = match short_sha(folder) {
+            Some(sha) => format!("integ.{}.{}", run_id, sha),
+            None => format!("integ.{}", run_id),
+        };
+        let version = ecosystem.stamp_version(folder, &stamp)?;
+        say!("{} stamped as {}", project.name, version);
+    }
+    // Install dependencies
+    let (result, duration) = event::step(&project.name, Step::Install, None, || {
+        with_retry(
+            &retry.install,
+            &format!("installing dependencies for {}", folder),
+            || ecosystem.install(folder, locked, seconds(timeouts.install)),
+        )
+    });
+    timings.install = Some(duration);
+    result?;
+    // Run the build
+    say!("Building {}", folder);
+    for command in project.repo.build_commands() {
+        let (result, duration) = event::step(&project.name, Step::Build, Some(command), || {
+            with_retry(&retry.build, &format!("{} in {}", command, folder), || {
+                command::run(
+                    Command::new("bash")
+                        .current_dir(folder)
+                        .envs(ecosystem.environment(folder))
+                        .arg("-c")
+                        .arg(command),
+                    seconds(timeouts.build),
+                )
+                .with_context(|| format!("Trying to build {} with {}", folder, command))?;
+                Ok(())
+            })
+        });
+        timings.build.push((command.clone(), duration));
+        result?;
+    }
+    // Create the package
+    let (result, duration) = event::step(&project.name, Step::Pack, None, || {
+        ecosystem.package(folder, seconds(timeouts.pack))
+    });
+    timings.pack = Some(duration);
+    let artifact = result?;
+    let size = fs::metadata(&artifact).map_or(0, |metadata| metadata.len());
+    timings.artifact_size = Some(size);
+    event::emit(Event::ArtifactProduced {
+        project: project.name.clone(),
+        path: artifact.clone(),
+        size,
+    });
+    if let Some(publish) = options.publish {
+        let (result, duration) = event::step(&project.name, Step::Publish, None, || {
+            with_retry(&retry.publish, &format!("publishing {}", artifact), || {
+                ecosystem.publish(
+                    folder,
+                    &artifact,
+                    &publish.registry,
+                    &publish.tag,
+                    seconds(timeouts.publish),
+                )
+            })
+        });
+        timings.publish = Some(duration);
+        result?;
+    }
+    Ok(artifact)
+}
+
+pub(crate) struct Project<'a> {
+    name: String,
+    repo: &'a Entry,
+    folder: &'a String,
+    ecosystem: &'static dyn Ecosystem,
+}
+
+pub(crate) fn coalesce_projects<'a>(
+    entries: &'a [Entry],
+    folders: &'a [String],
+    manifests: &'a [Manifest],
+) -> HashMap<String, Project<'a>> {
+    assert_eq!(entries.len(), folders.len());
+    assert_eq!(entries.len(), manifests.len());
+    entries
+        .iter()
+        .zip(folders)
+        .zip(manifests)
+        .map(|((repo, folder), manifest)| Project {
+            name: manifest.name.clone(),
+            repo,
+            folder,
+            ecosystem: ecosystem(repo.ecosystem()),
+        })
+        .fold(HashMap::new(), |mut acc, project| {
+            acc.insert(project.name.clone(), project);
+            acc
+        })
+}
+
+/// The outcome of the build of a project.
+#[derive(Debug)]
+pub enum BuildStatus {
+    Succeeded,
+    /// The package from a previous run is more recent than its dependencies
+    Cached,
+    Failed(anyhow::Error),
+    /// Not built because these dependencies failed or were blocked themselves
+    Blocked(Vec<String>),
+}
+
+impl BuildStatus {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, BuildStatus::Succeeded | BuildStatus::Cached)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BuildStatus::Succeeded => "succeeded",
+            BuildStatus::Cached => "cached",
+            BuildStatus::Failed(_) => "failed",
+            BuildStatus::Blocked(_) => "blocked",
+        }
+    }
+}
+
+/// How long the steps of a project took in a run, the ones it did not go
+/// through being None.
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    /// The clone or copy of the project
+    pub retrieve: Option<Duration>,
+    pub install: Option<Duration>,
+    /// Each build command with its duration
+    pub build: Vec<(String, Duration)>,
+    pub pack: Option<Duration>,
+    pub publish: Option<Duration>,
+    /// The size of the artifact in bytes, built in this run or before
+    pub artifact_size: Option<u64>,
+}
+
+impl Timings {
+    pub fn total(&self) -> Duration {
+        let build = self.build.iter().map(|(_, duration)| *duration).sum();
+        [
+            self.retrieve,
+            self.install,
+            Some(build),
+            self.pack,
+            self.publish,
+        ]
+        .iter()
+        .flatten()
+        .sum()
+    }
+}
+
+/// The outcome of the builds.
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    /// The status of each project, in build order
+    pub statuses: Vec<(String, BuildStatus)>,
+    /// How long the steps of each project took, by project name
+    pub timings: HashMap<String, Timings>,
+    /// The chain of dependent projects which took the longest, see
+    /// [`crate::graph::critical_path`]
+    pub critical_path: Vec<String>,
+}
+
+impl BuildReport {
+    pub fn iter(&self) -> std::slice::Iter<'_, (String, BuildStatus)> {
+        self.statuses.iter()
+    }
+
+    /// Whether every project succeeded or was cached.
+    pub fn is_ok(&self) -> bool {
+        self.iter().all(|(_, status)| status.is_ok())
+    }
+
+    /// Find the critical path of the projects linked by `graph` as per
+    /// their timings.
+    pub(crate) fn update_critical_path(&mut self, graph: &Graph) {
+        let order = self
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        let durations = self
+            .timings
+            .iter()
+            .map(|(name, timings)| (name.clone(), timings.total()))
+            .collect();
+        self.critical_path = critical_path(graph, &order, &durations);
+    }
+}
+
+fn blocked_by(dependencies: &[String], statuses: &[(String, BuildStatus)]) -> Vec<String> {
+    statuses
+        .iter()
+        .filter(|(name, status)| {
+            dependencies.contains(name)
+                && matches!(status, BuildStatus::Failed(_) | BuildStatus::Blocked(_))
+        })
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+// The time `path` was last modified
+fn modified(path: &str) -> Result<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Trying to read the modification time of {}", path))
+}
+
+// Whether the package of a project is more recent than the packages of its
+// dependencies, in which case it is not rebuilt. A package which is missing
+// is rebuilt.
+fn is_up_to_date(package: &str, dependency_packages: &[&str]) -> Result<bool> {
+    if !Path::new(package).exists() {
+        return Ok(false);
+    }
+    let mut newest_dependency = None;
+    for filepath in dependency_packages {
+        newest_dependency = newest_dependency.max(Some(modified(filepath)?));
+    }
+    Ok(match newest_dependency {
+        None => true,
+        Some(time) => time < modified(package)?,
+    })
+}
+
+/// What a build would do with a project.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Build,
+    /// Keep the package of a previous run
+    Reuse,
+}
+
+/// The action a build would take for each project, in build order.
+pub(crate) fn plan(
+    output_path: &Path,
+    graph: &Graph,
+    order: &[String],
+) -> Result<Vec<(String, Action)>> {
+    let progress_path = output_path.join("integ.progress");
+    let package_paths = load_package_paths(&progress_path.to_string_lossy()).unwrap_or_default();
+    let mut actions: Vec<(String, Action)> = vec![];
+    for project_name in order {
+        let dependencies = graph.get(project_name).unwrap();
+        // A dependency rebuilt gets a package more recent than the project's
+        let rebuilt = actions
+            .iter()
+            .any(|(name, action)| dependencies.contains(name) && *action == Action::Build);
+        let dependency_files = dependencies
+            .iter()
+            .filter_map(|d| package_paths.get(d).map(String::as_str))
+            .collect::<Vec<&str>>();
+        let action = match package_paths.get(project_name) {
+            Some(package) if !rebuilt && is_up_to_date(package, &dependency_files)? => {
+                Action::Reuse
+            }
+            _ => Action::Build,
+        };
+        actions.push((project_name.clone(), action));
+    }
+    Ok(actions)
+}
+
+// The versions the dependencies were built with, stamped or not, in this run
+// or before
+fn built_versions(
+    projects: &HashMap<String, Project>,
+    dependencies: &[String],
+) -> Result<Vec<(String, String)>> {
+    dependencies
+        .iter()
+        .map(|d| {
+            let dependency = projects.get(d).unwrap();
+            let manifest = dependency.ecosystem.manifest(dependency.folder)?;
+            Ok((d.clone(), manifest.version.unwrap_or_default()))
+        })
+        .collect()
+}
+
+pub(crate) fn build_all(
+    output_path: &Path,
+    projects: &HashMap<String, Project>,
+    graph: &Graph,
+    order: &[String],
+    options: &BuildOptions,
+) -> Result<BuildReport> {
+    let progress_path = output_path.join("integ.progress");
+    let progress_file = progress_path.to_string_lossy();
+    let mut package_paths = load_package_paths(&progress_file).unwrap_or_default();
+    let failures_path = output_path.join("integ.failures");
+    let failures_file = failures_path.to_string_lossy();
+    let mut failures = load_failures(&failures_file).unwrap_or_default();
+    let mut record_failure = |project_name: &str, error: Option<&anyhow::Error>| {
+        match error {
+            Some(error) => failures.insert(project_name.to_string(), first_line(error)),
+            None => failures.remove(project_name),
+        };
+        dump_failures(&failures_file, &failures)
+            .with_context(|| format!("Fail while trying to save failures in {}", failures_file))
+    };
+    let mut report = BuildReport::default();
+    for project_name in order {
+        let dependencies = graph.get(project_name).unwrap();
+        let blocking = blocked_by(dependencies, &report.statuses);
+        if !blocking.is_empty() {
+            say!("{} blocked by {}", project_name, blocking.join(", "));
+            finish(&mut report, project_name, BuildStatus::Blocked(blocking));
+            continue;
+        }
+        let dependency_packages = dependencies
+            .iter()
+            .map(|d| (d.clone(), package_paths.get(d).unwrap().clone()))
+            .collect::<Vec<(String, String)>>();
+
+        if let Some(project_package_file) = package_paths.get(project_name) {
+            let dependency_files = dependency_packages
+                .iter()
+                .map(|(_, filepath)| filepath.as_str())
+                .collect::<Vec<&str>>();
+            if is_up_to_date(project_package_file, &dependency_files)? {
+                let timings = report.timings.entry(project_name.clone()).or_default();
+                timings.artifact_size = fs::metadata(project_package_file)
+                    .ok()
+                    .map(|metadata| metadata.len());
+                finish(&mut report, project_name, BuildStatus::Cached);
+                continue;
+            }
+        }
+        let project = projects.get(project_name).unwrap();
+        let timings = report.timings.entry(project_name.clone()).or_default();
+        // A manifest which cannot be read fails the project like its build
+        let package_path = match built_versions(projects, dependencies).and_then(|versions| {
+            build_and_package(project, &dependency_packages, &versions, options, timings)
+        }) {
+            Ok(package_path) => package_path,
+            Err(error) if options.keep_going => {
+                event::warn(format_args!("{} failed: {:?}", project_name, error));
+                record_failure(project_name, Some(&error))?;
+                finish(&mut report, project_name, BuildStatus::Failed(error));
+                continue;
+            }
+            Err(error) => {
+                record_failure(project_name, Some(&error))?;
+                emit_finished(project_name, "failed", Some(&error));
+                return Err(error);
+            }
+        };
+        record_failure(project_name, None)?;
+        package_paths.insert(project_name.clone(), package_path.clone());
+        dump_package_paths(&progress_file, &package_paths)
+            .with_context(|| format!("Fail while trying to save progress in {}", progress_file))?;
+        finish(&mut report, project_name, BuildStatus::Succeeded);
+    }
+    Ok(report)
+}
+
+fn emit_finished(project_name: &str, status: &'static str, error: Option<&anyhow::Error>) {
+    event::emit(Event::ProjectFinished {
+        project: project_name.to_string(),
+        status,
+        error: error.map(first_line),
+    });
+}
+
+// Report the status of a project once it is known
+fn finish(report: &mut BuildReport, project_name: &str, status: BuildStatus) {
+    let error = match &status {
+        BuildStatus::Failed(error) => Some(error),
+        _ => None,
+    };
+    emit_finished(project_name, status.label(), error);
+    report.statuses.push((project_name.to_string(), status));
+}
+
+fn first_line(error: &anyhow::Error) -> String {
+    format!("{:#}", error)
+        .lines()
+        .next()
+        .unwrap_or("")
+        .to_string()
+}
+
+fn format_duration(duration: Option<Duration>) -> String {
+    match duration {
+        Some(duration) => format!("{:.1}s", duration.as_secs_f64()),
+        None => String::from("-"),
+    }
+}
+
+fn format_size(size: Option<u64>) -> String {
+    match size {
+        Some(size) if size >= 1 << 20 => format!("{:.1} MiB", size as f64 / (1 << 20) as f64),
+        Some(size) if size >= 1 << 10 => format!("{:.1} KiB", size as f64 / (1 << 10) as f64),
+        Some(size) => format!("{} B", size),
+        None => String::from("-"),
+    }
+}
+
+/// A table of how long each step of each project took, each build command
+/// on its own line, followed by the critical path.
+pub fn format_timings(report: &BuildReport) -> String {
+    let no_timings = Timings::default();
+    let mut rows = vec![];
+    for (name, status) in report.iter() {
+        let timings = report.timings.get(name).unwrap_or(&no_timings);
+        let build = match timings.build.is_empty() {
+            true => None,
+            false => Some(timings.build.iter().map(|(_, duration)| *duration).sum()),
+        };
+        rows.push([
+            name.clone(),
+            status.label().to_string(),
+            format_duration(timings.retrieve),
+            format_duration(timings.install),
+            format_duration(build),
+            format_duration(timings.pack),
+            format_duration(Some(timings.total())),
+            format_size(timings.artifact_size),
+        ]);
+        for (command, duration) in &timings.build {
+            let mut row: [String; 8] = Default::default();
+            row[0] = format!("  {}", command);
+            row[4] = format_duration(Some(*duration));
+            rows.push(row);
+        }
+    }
+    let header = [
+        "project", "status", "retrieve", "install", "build", "pack", "total", "artifact",
+    ]
+    .map(String::from);
+    let mut widths = header.clone().map(|title| title.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let mut line = String::new();
+        for (index, cell) in row.iter().enumerate() {
+            // Text columns are aligned left, durations and sizes right
+            let cell = match index {
+                0 | 1 => format!("{:width$}  ", cell, width = widths[index]),
+                _ => format!("{:>width$}  ", cell, width = widths[index]),
+            };
+            line += &cell;
+        }
+        table += line.trim_end();
+        table += "\n";
+    }
+    let total: Duration = report
+        .critical_path
+        .iter()
+        .filter_map(|name| report.timings.get(name))
+        .map(Timings::total)
+        .sum();
+    // Nothing to speed up if everything was cached
+    if !total.is_zero() {
+        table += &format!(
+            "critical path: {} ({})\n",
+            report.critical_path.join(" -> "),
+            format_duration(Some(total))
+        );
+    }
+    table
+}
+
+/// A table of the status of each project followed by the count per status.
+pub fn format_summary(report: &BuildReport) -> String {
+    let rows = report
+        .iter()
+        .map(|(name, status)| {
+            let detail = match status {
+                BuildStatus::Succeeded | BuildStatus::Cached => String::new(),
+                // The full error has already been printed, keep the first line
+                BuildStatus::Failed(error) => first_line(error),
+                BuildStatus::Blocked(by) => format!("by {}", by.join(", ")),
+            };
+            (name.as_str(), status.label(), detail)
+        })
+        .collect::<Vec<_>>();
+    let width = rows
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .chain(std::iter::once("project".len()))
+        .max()
+        .unwrap();
+    let mut summary = format!("{:width$}  {:9}  detail\n", "project", "status");
+    for (name, status, detail) in rows {
+        summary += format!("{:width$}  {:9}  {}", name, status, detail).trim_end();
+        summary += "\n";
+    }
+    let count = |label: &str| {
+        report
+            .iter()
+            .filter(|(_, status)| status.label() == label)
+            .count()
+    };
+    summary += &format!(
+        "{} succeeded, {} cached, {} failed, {} blocked\n",
+        count("succeeded"),
+        count("cached"),
+        count("failed"),
+        count("blocked")
+    );
+    summary
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_build_report() {
+        let statuses = vec![
+            (String::from("a"), BuildStatus::Cached),
+            (
+                String::from("b"),
+                BuildStatus::Failed(anyhow::anyhow!("exited with code 1\nnpm ERR!")),
+            ),
+            (String::from("c"), BuildStatus::Succeeded),
+            (
+                String::from("d"),
+                BuildStatus::Blocked(vec![String::from("b")]),
+            ),
+        ];
+        assert_eq!(
+            blocked_by(&[String::from("a"), String::from("c")], &statuses),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            blocked_by(&[String::from("a"), String::from("d")], &statuses),
+            vec![String::from("d")]
+        );
+        let report = BuildReport {
+            statuses,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_summary(&report),
+            "project  status     detail\n\
+             a        cached\n\
+             b        failed     exited with code 1\n\
+             c        succeeded\n\
+             d        blocked    by b\n\
+             1 succeeded, 1 cached, 1 failed, 1 blocked\n"
+        );
+    }
+
+    #[test]
+    fn test_plan() {
+        let root = tempfile::tempdir().unwrap();
+        let package = |name: &str, age: u64| {
+            let path = root.path().join(format!("{}.tgz", name));
+            let file = fs::File::create(&path).unwrap();
+            let modified = SystemTime::now() - std::time::Duration::from_secs(age);
+            file.set_modified(modified).unwrap();
+            (String::from(name), path.to_string_lossy().to_string())
+        };
+        // b was packaged before a, c was never built
+        let package_paths = vec![package("a", 10), package("b", 20)]
+            .into_iter()
+            .collect();
+        dump_package_paths(
+            &root.path().join("integ.progress").to_string_lossy(),
+            &package_paths,
+        )
+        .unwrap();
+        let graph = vec![
+            (String::from("a"), vec![]),
+            (String::from("b"), vec![String::from("a")]),
+            (String::from("c"), vec![String::from("b")]),
+            (String::from("d"), vec![]),
+        ]
+        .into_iter()
+        .collect::<Graph>();
+        let order = ["a", "b", "c", "d"].map(String::from);
+        assert_eq!(
+            plan(root.path(), &graph, &order).unwrap(),
+            vec![
+                (String::from("a"), Action::Reuse),
+                (String::from("b"), Action::Build),
+                (String::from("c"), Action::Build),
+                (String::from("d"), Action::Build),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_timings() {
+        let seconds = |tenths: u64| Some(Duration::from_millis(tenths * 100));
+        let mut report = BuildReport {
+            statuses: vec![
+                (String::from("a"), BuildStatus::Cached),
+                (String::from("b"), BuildStatus::Succeeded),
+            ],
+            ..Default::default()
+        };
+        report.timings.insert(
+            String::from("a"),
+            Timings {
+                artifact_size: Some(512),
+                ..Default::default()
+            },
+        );
+        report.timings.insert(
+            String::from("b"),
+            Timings {
+                retrieve: seconds(12),
+                install: seconds(35),
+                build: vec![
+                    (String::from("npm test"), seconds(20).unwrap()),
+                    (String::from("npm run build"), seconds(11).unwrap()),
+                ],
+                pack: seconds(4),
+                publish: None,
+                artifact_size: Some(20480),
+            },
+        );
+        let graph = vec![
+            (String::from("a"), vec![]),
+            (String::from("b"), vec![String::from("a")]),
+        ]
+        .into_iter()
+        .collect::<Graph>();
+        report.update_critical_path(&graph);
+        assert_eq!(
+            format_timings(&report),
+            "project          status     retrieve  install  build  pack  total  artifact\n\
+             a                cached            -        -      -     -   0.0s     512 B\n\
+             b                succeeded      1.2s     3.5s   3.1s  0.4s   8.2s  20.0 KiB\n  \
+             npm test                                      2.0s\n  \
+             npm run build                                 1.1s\n\
+             critical path: a -> b (8.2s)\n"
+        );
+    }
+}
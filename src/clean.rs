@@ -0,0 +1,292 @@
+//! Deletion of what previous runs left in the output folder, keeping the
+//! progress state in line with what remains.
+use crate::config::Entry;
+use crate::ecosystem::ecosystem;
+use crate::progress::{dump_failures, dump_package_paths, load_failures, load_package_paths};
+use crate::validate::suggest;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// What `integ clean` deletes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CleanLevel {
+    /// The packages built, which are rebuilt by the next run
+    Artifacts,
+    /// The installed dependencies, e.g. `node_modules`
+    Dependencies,
+    /// The folders of the projects, which are retrieved again
+    Projects,
+    /// The folders of the projects and, for all of them, the progress
+    /// files, i.e. everything integ created in the output folder
+    All,
+}
+
+impl CleanLevel {
+    pub const NAMES: [&'static str; 4] = ["artifacts", "dependencies", "projects", "all"];
+}
+
+impl FromStr for CleanLevel {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level {
+            "artifacts" => Ok(CleanLevel::Artifacts),
+            "dependencies" => Ok(CleanLevel::Dependencies),
+            "projects" => Ok(CleanLevel::Projects),
+            "all" => Ok(CleanLevel::All),
+            _ => Err(format!(
+                "unknown level `{}`, expected one of {}",
+                level,
+                CleanLevel::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+// A project of the configuration with the package name it was built under,
+// if it can still be read
+struct Target<'a> {
+    entry: &'a Entry,
+    folder: &'a str,
+    package: Option<String>,
+}
+
+// The projects named by their folder or package name, all of them if none
+fn select<'a>(
+    entries: &'a [Entry],
+    folders: &'a [String],
+    selected: &[String],
+) -> Result<Vec<Target<'a>>> {
+    let targets = entries
+        .iter()
+        .zip(folders)
+        .map(|(entry, folder)| Target {
+            entry,
+            folder,
+            package: ecosystem(entry.ecosystem())
+                .manifest(folder)
+                .ok()
+                .map(|manifest| manifest.name),
+        })
+        .collect::<Vec<_>>();
+    if selected.is_empty() {
+        return Ok(targets);
+    }
+    for name in selected {
+        let known = targets.iter().any(|target| {
+            target.entry.folder_name() == name || target.package.as_ref() == Some(name)
+        });
+        if !known {
+            let names = targets.iter().flat_map(|target| {
+                std::iter::once(target.entry.folder_name()).chain(target.package.as_deref())
+            });
+            let hint = match suggest(name, names) {
+                Some(suggestion) => format!(", did you mean `{}`?", suggestion),
+                None => String::new(),
+            };
+            return Err(anyhow::anyhow!("no project named `{}`{}", name, hint));
+        }
+    }
+    Ok(targets
+        .into_iter()
+        .filter(|target| {
+            selected.iter().any(|name| {
+                target.entry.folder_name() == name || target.package.as_ref() == Some(name)
+            })
+        })
+        .collect())
+}
+
+fn remove(path: &Path, removed: &mut Vec<String>) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+    .with_context(|| format!("Trying to remove {}", path.to_string_lossy()))?;
+    removed.push(path.to_string_lossy().to_string());
+    Ok(())
+}
+
+/// Delete what `level` designates for the `selected` projects (all of them
+/// if empty) and return the paths removed. The packages and failures of the
+/// projects whose artifacts or folders are deleted are forgotten. `force`
+/// deletes the whole output path instead, whatever it contains.
+pub(crate) fn clean(
+    output_path: &Path,
+    entries: &[Entry],
+    folders: &[String],
+    selected: &[String],
+    level: CleanLevel,
+    force: bool,
+) -> Result<Vec<String>> {
+    let mut removed = vec![];
+    if force {
+        if level != CleanLevel::All || !selected.is_empty() {
+            return Err(anyhow::anyhow!(
+                "the whole output path is only deleted at level `all` for all the projects"
+            ));
+        }
+        remove(output_path, &mut removed)?;
+        return Ok(removed);
+    }
+    let targets = select(entries, folders, selected)?;
+    let progress_path = output_path.join("integ.progress");
+    let progress_file = progress_path.to_string_lossy();
+    let mut package_paths = load_package_paths(&progress_file).unwrap_or_default();
+    let failures_path = output_path.join("integ.failures");
+    let failures_file = failures_path.to_string_lossy();
+    let mut failures = load_failures(&failures_file).unwrap_or_default();
+    for target in &targets {
+        match level {
+            CleanLevel::Artifacts => {
+                if let Some(package) = target
+                    .package
+                    .as_ref()
+                    .and_then(|package| package_paths.remove(package))
+                {
+                    remove(Path::new(&package), &mut removed)?;
+                }
+            }
+            CleanLevel::Dependencies => {
+                for dependencies in ecosystem(target.entry.ecosystem()).dependency_folders() {
+                    remove(&Path::new(target.folder).join(dependencies), &mut removed)?;
+                }
+            }
+            CleanLevel::Projects | CleanLevel::All => {
+                remove(Path::new(target.folder), &mut removed)?;
+                if let Some(package) = &target.package {
+                    package_paths.remove(package);
+                    failures.remove(package);
+                }
+            }
+        }
+    }
+    if level == CleanLevel::All && selected.is_empty() {
+        remove(&progress_path, &mut removed)?;
+        remove(&failures_path, &mut removed)?;
+        return Ok(removed);
+    }
+    if progress_path.exists() {
+        dump_package_paths(&progress_file, &package_paths)
+            .with_context(|| format!("Fail while trying to save progress in {}", progress_file))?;
+    }
+    if failures_path.exists() {
+        dump_failures(&failures_file, &failures)
+            .with_context(|| format!("Fail while trying to save failures in {}", failures_file))?;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Folder;
+
+    #[test]
+    fn test_clean() {
+        let root = tempfile::tempdir().unwrap();
+        let output = root.path().join("out");
+        let mut entries = vec![];
+        let mut package_paths = std::collections::HashMap::new();
+        for name in ["a", "b"] {
+            let folder = output.join(name);
+            fs::create_dir_all(folder.join("node_modules")).unwrap();
+            fs::write(
+                folder.join("package.json"),
+                format!(r#"{{ "name": "@x/{}", "version": "1.0.0" }}"#, name),
+            )
+            .unwrap();
+            let package = folder.join(format!("x-{}-1.0.0.tgz", name));
+            fs::write(&package, "").unwrap();
+            package_paths.insert(
+                format!("@x/{}", name),
+                package.to_string_lossy().to_string(),
+            );
+            entries.push(Entry::Folder(Folder {
+                path: format!("/src/{}", name),
+                build: vec![],
+                profile: None,
+                ecosystem: Default::default(),
+                name: None,
+            }));
+        }
+        let progress = output.join("integ.progress");
+        dump_package_paths(&progress.to_string_lossy(), &package_paths).unwrap();
+        let folders = crate::retrieve::folder_names(&output, &entries);
+
+        let error = clean(
+            &output,
+            &entries,
+            &folders,
+            &[String::from("c")],
+            CleanLevel::All,
+            false,
+        );
+        assert!(error.is_err());
+
+        let removed = clean(
+            &output,
+            &entries,
+            &folders,
+            &[],
+            CleanLevel::Dependencies,
+            false,
+        )
+        .unwrap();
+        assert_eq!(removed.len(), 2);
+        assert!(!output.join("a").join("node_modules").exists());
+
+        let selected = [String::from("@x/b")];
+        clean(
+            &output,
+            &entries,
+            &folders,
+            &selected,
+            CleanLevel::Artifacts,
+            false,
+        )
+        .unwrap();
+        let remaining = load_package_paths(&progress.to_string_lossy()).unwrap();
+        assert_eq!(remaining.keys().collect::<Vec<_>>(), vec!["@x/a"]);
+        assert!(!output.join("b").join("x-b-1.0.0.tgz").exists());
+
+        let selected = [String::from("a")];
+        clean(
+            &output,
+            &entries,
+            &folders,
+            &selected,
+            CleanLevel::Projects,
+            false,
+        )
+        .unwrap();
+        assert!(!output.join("a").exists());
+        assert!(load_package_paths(&progress.to_string_lossy())
+            .unwrap()
+            .is_empty());
+        assert!(output.join("b").exists());
+
+        // Only what integ created is deleted without force
+        fs::write(output.join("notes.txt"), "").unwrap();
+        let removed = clean(&output, &entries, &folders, &[], CleanLevel::All, false).unwrap();
+        assert_eq!(
+            removed,
+            vec![
+                output.join("b").to_string_lossy().to_string(),
+                progress.to_string_lossy().to_string(),
+            ]
+        );
+        assert!(output.join("notes.txt").exists());
+        let error = clean(&output, &entries, &folders, &[], CleanLevel::Projects, true);
+        assert!(error.is_err());
+        assert!(output.exists());
+        clean(&output, &entries, &folders, &[], CleanLevel::All, true).unwrap();
+        assert!(!output.exists());
+    }
+}
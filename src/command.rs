@@ -0,0 +1,389 @@
+//! Spawning of the external tools (git, npm, bash, sed, ...) used by integ.
+//!
+//! Every command goes through [`run`] so that a missing binary, a failing or a
+//! hanging command are all reported as a [`CommandError`] instead of a panic.
+use std::fmt;
+use std::io::{self, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub enum CommandError {
+    ToolNotFound {
+        tool: String,
+    },
+    NonZeroExit {
+        code: i32,
+        stdout: String,
+        stderr: String,
+    },
+    Signaled {
+        signal: i32,
+        stdout: String,
+        stderr: String,
+    },
+    Timeout {
+        timeout: Duration,
+        stdout: String,
+        stderr: String,
+    },
+    Io(io::Error),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn write_output(f: &mut fmt::Formatter, stdout: &str, stderr: &str) -> fmt::Result {
+            for output in [stdout.trim_end(), stderr.trim_end()] {
+                if !output.is_empty() {
+                    write!(f, "\n{}", output)?;
+                }
+            }
+            Ok(())
+        }
+
+        match self {
+            CommandError::ToolNotFound { tool } => {
+                write!(f, "`{}` not found, is it installed and in the PATH?", tool)
+            }
+            CommandError::NonZeroExit {
+                code,
+                stdout,
+                stderr,
+            } => {
+                write!(f, "exited with code {}", code)?;
+                write_output(f, stdout, stderr)
+            }
+            CommandError::Signaled {
+                signal,
+                stdout,
+                stderr,
+            } => {
+                write!(f, "killed by signal {}", signal)?;
+                write_output(f, stdout, stderr)
+            }
+            CommandError::Timeout {
+                timeout,
+                stdout,
+                stderr,
+            } => {
+                write!(f, "timed out after {}s", timeout.as_secs())?;
+                write_output(f, stdout, stderr)
+            }
+            CommandError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CommandError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+fn read_to_string_lossy(mut reader: impl Read + Send + 'static) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut buffer = vec![];
+        reader.read_to_end(&mut buffer).ok();
+        String::from_utf8_lossy(&buffer).into_owned()
+    })
+}
+
+fn wait(child: &mut Child, timeout: Option<Duration>) -> io::Result<Option<ExitStatus>> {
+    let deadline = match timeout {
+        Some(timeout) => Instant::now() + timeout,
+        None => return child.wait().map(Some),
+    };
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            kill(child)?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+// Start the command in a process group of its own, so that the processes it
+// spawns can be killed along with it
+#[cfg(unix)]
+fn isolate(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    command.process_group(0);
+}
+
+#[cfg(not(unix))]
+fn isolate(_command: &mut Command) {}
+
+// Kill the child and, where it has its own process group, whatever it spawned
+// which could otherwise keep the pipes open
+#[cfg(unix)]
+fn kill(child: &mut Child) -> io::Result<()> {
+    // The group id is the pid of the child, see isolate
+    if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } == 0 {
+        return Ok(());
+    }
+    child.kill()
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) -> io::Result<()> {
+    child.kill()
+}
+
+/// Run `command` to completion, capturing its output.
+///
+/// The command is killed if it is still running after `timeout`.
+pub fn run(command: &mut Command, timeout: Option<Duration>) -> Result<Output, CommandError> {
+    let tool = command.get_program().to_string_lossy().into_owned();
+    // A missing working directory is also reported as NotFound by spawn
+    if let Some(dir) = command.get_current_dir() {
+        if !dir.exists() {
+            return Err(CommandError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not exist", dir.to_string_lossy()),
+            )));
+        }
+    }
+    // Without a timeout the command stays in our group to receive Ctrl-C
+    if timeout.is_some() {
+        isolate(command);
+    }
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| match error.kind() {
+            io::ErrorKind::NotFound => CommandError::ToolNotFound { tool },
+            _ => CommandError::Io(error),
+        })?;
+    let stdout = read_to_string_lossy(child.stdout.take().unwrap());
+    let stderr = read_to_string_lossy(child.stderr.take().unwrap());
+    let status = wait(&mut child, timeout).map_err(CommandError::Io)?;
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+
+    match status {
+        None => Err(CommandError::Timeout {
+            timeout: timeout.unwrap_or_default(),
+            stdout,
+            stderr,
+        }),
+        Some(status) if status.success() => Ok(Output { stdout, stderr }),
+        Some(status) => match (status.code(), signal(&status)) {
+            (_, Some(signal)) => Err(CommandError::Signaled {
+                signal,
+                stdout,
+                stderr,
+            }),
+            (code, None) => Err(CommandError::NonZeroExit {
+                code: code.unwrap_or(-1),
+                stdout,
+                stderr,
+            }),
+        },
+    }
+}
+
+/// Whether `tool` can be spawned at all, whatever its exit code.
+pub fn is_available(tool: &str) -> bool {
+    !matches!(
+        run(Command::new(tool).arg("--version"), None),
+        Err(CommandError::ToolNotFound { .. }) | Err(CommandError::Io(_))
+    )
+}
+
+pub struct Requirement {
+    pub tool: &'static str,
+    pub args: &'static [&'static str],
+    // Minimal version as found in the output of `tool args`, if any
+    pub min_version: Option<(u64, u64, u64)>,
+    // Why integ needs this tool, for the error message
+    pub reason: String,
+}
+
+// Extract the first `major.minor[.patch]` found in `text`
+fn parse_version(text: &str) -> Option<(u64, u64, u64)> {
+    text.split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .filter_map(|word| {
+            let mut numbers = word.split('.').map(|n| n.parse::<u64>());
+            match (numbers.next(), numbers.next(), numbers.next()) {
+                (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch))) => Some((major, minor, patch)),
+                (Some(Ok(major)), Some(Ok(minor)), None) => Some((major, minor, 0)),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+/// Check that all the required tools are installed in a recent enough
+/// version, reporting all the problems at once.
+pub fn preflight(requirements: &[Requirement]) -> anyhow::Result<()> {
+    let mut problems = vec![];
+    for requirement in requirements {
+        let output = run(Command::new(requirement.tool).args(requirement.args), None);
+        let min_version = match requirement.min_version {
+            Some(min_version) => min_version,
+            None => {
+                if let Err(CommandError::ToolNotFound { .. }) = output {
+                    problems.push(format!(
+                        "`{}` is required {} but is not installed",
+                        requirement.tool, requirement.reason
+                    ));
+                }
+                continue;
+            }
+        };
+        let command = format!("{} {}", requirement.tool, requirement.args.join(" "));
+        match output {
+            Err(CommandError::ToolNotFound { .. }) => problems.push(format!(
+                "`{}` is required {} but is not installed",
+                requirement.tool, requirement.reason
+            )),
+            Err(error) => problems.push(format!(
+                "`{}` is required {} but `{}` failed: {}",
+                requirement.tool, requirement.reason, command, error
+            )),
+            // Some tools print their version on stderr
+            Ok(output) => match parse_version(&output.stdout).or(parse_version(&output.stderr)) {
+                Some(version) if version < min_version => problems.push(format!(
+                    "`{}` {}.{}.{} is too old, {} needs at least {}.{}.{}",
+                    command,
+                    version.0,
+                    version.1,
+                    version.2,
+                    requirement.reason,
+                    min_version.0,
+                    min_version.1,
+                    min_version.2
+                )),
+                Some(_) => (),
+                None => problems.push(format!(
+                    "could not read the version of `{}` from `{}`",
+                    requirement.tool,
+                    output.stdout.trim()
+                )),
+            },
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "missing requirements:\n  {}",
+            problems.join("\n  ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let output = run(
+            Command::new("bash").args(["-c", "echo out; echo err >&2"]),
+            None,
+        )
+        .unwrap();
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+
+        match run(
+            Command::new("bash").args(["-c", "echo oops >&2; exit 3"]),
+            None,
+        ) {
+            Err(CommandError::NonZeroExit { code, stderr, .. }) => {
+                assert_eq!(code, 3);
+                assert_eq!(stderr, "oops\n");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match run(&mut Command::new("integ-no-such-tool"), None) {
+            Err(CommandError::ToolNotFound { tool }) => assert_eq!(tool, "integ-no-such-tool"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // The sleep is a grandchild which must be killed along with bash
+        let start = Instant::now();
+        match run(
+            Command::new("bash").args(["-c", "sleep 5; true"]),
+            Some(Duration::from_millis(100)),
+        ) {
+            Err(CommandError::Timeout { .. }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // Invalid UTF-8 is decoded lossily
+        let output = run(Command::new("bash").args(["-c", r"printf '\xff'"]), None).unwrap();
+        assert_eq!(output.stdout, "\u{fffd}");
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("git version 2.39.5"), Some((2, 39, 5)));
+        assert_eq!(parse_version("10.2.4\n"), Some((10, 2, 4)));
+        assert_eq!(
+            parse_version("git-lfs/3.3.0 (GitHub; linux amd64; go 1.19.8)"),
+            Some((3, 3, 0))
+        );
+        assert_eq!(parse_version("rsync  version 3.2"), Some((3, 2, 0)));
+        assert_eq!(parse_version("no version"), None);
+    }
+
+    #[test]
+    fn test_preflight() {
+        assert!(preflight(&[Requirement {
+            tool: "bash",
+            args: &["--version"],
+            min_version: Some((1, 0, 0)),
+            reason: String::from("to run the tests"),
+        }])
+        .is_ok());
+        let error = preflight(&[
+            Requirement {
+                tool: "bash",
+                args: &["--version"],
+                min_version: Some((999, 0, 0)),
+                reason: String::from("to run the tests"),
+            },
+            Requirement {
+                tool: "integ-no-such-tool",
+                args: &[],
+                min_version: None,
+                reason: String::from("to run the tests"),
+            },
+        ])
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("too old"));
+        assert!(error.contains("`integ-no-such-tool` is required to run the tests"));
+    }
+}
@@ -0,0 +1,632 @@
+//! The integ configuration file, listing the projects to integrate.
+use crate::retrieve::{local_folder, repo_folder};
+use crate::validate::suggest;
+use anyhow::{Context, Result};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How the submodules of a repository are fetched.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Submodules {
+    #[default]
+    Disabled,
+    /// Only the first level of submodules
+    Enabled,
+    Recursive,
+}
+
+// Accepts `submodules: true|false|recursive`
+impl<'de> Deserialize<'de> for Submodules {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Flag(bool),
+            Mode(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Flag(false) => Ok(Submodules::Disabled),
+            Raw::Flag(true) => Ok(Submodules::Enabled),
+            Raw::Mode(mode) if mode == "recursive" => Ok(Submodules::Recursive),
+            Raw::Mode(mode) => Err(serde::de::Error::custom(format!(
+                "invalid submodules value `{}`, expected true, false or recursive",
+                mode
+            ))),
+        }
+    }
+}
+
+impl JsonSchema for Submodules {
+    fn schema_name() -> String {
+        String::from("Submodules")
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        serde_json::from_value(serde_json::json!({
+            "description": "Fetch the submodules, only the first level with `true`",
+            "oneOf": [{ "type": "boolean" }, { "enum": ["recursive"] }],
+        }))
+        .unwrap()
+    }
+}
+
+/// The package manager of a project.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EcosystemKind {
+    #[default]
+    Npm,
+    Cargo,
+    Python,
+}
+
+/// The section of the manifest a dependency is declared in.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyKind {
+    Normal,
+    Dev,
+    Peer,
+    Optional,
+    Build,
+}
+
+fn default_edges() -> Vec<DependencyKind> {
+    vec![
+        DependencyKind::Normal,
+        DependencyKind::Dev,
+        DependencyKind::Peer,
+        DependencyKind::Optional,
+        DependencyKind::Build,
+    ]
+}
+
+/// What to do when the version of an integrated package is out of the range
+/// declared by one of its dependents.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionCheck {
+    Ignore,
+    #[default]
+    Warn,
+    Error,
+}
+
+/// A project cloned from a git repository.
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct Repo {
+    pub url: String,
+    pub branch: String,
+    /// Commands run with bash in the project folder once its dependencies are
+    /// installed, after the ones of the profile
+    #[serde(default)]
+    pub build: Vec<String>,
+    /// The name of a build profile
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub submodules: Submodules,
+    /// Pull Git LFS objects after cloning
+    #[serde(default)]
+    pub lfs: bool,
+    #[serde(default)]
+    pub ecosystem: EcosystemKind,
+}
+
+/// A project copied from a local folder.
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct Folder {
+    pub path: String,
+    /// Commands run with bash in the project folder once its dependencies are
+    /// installed, after the ones of the profile
+    #[serde(default)]
+    pub build: Vec<String>,
+    /// The name of a build profile
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub ecosystem: EcosystemKind,
+    /// The folder the project is copied into when it replaces an entry from
+    /// the command line, the last component of its path otherwise
+    #[serde(skip)]
+    pub name: Option<String>,
+}
+
+fn default_workers() -> usize {
+    1
+}
+
+/// A project to integrate.
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(untagged)]
+pub enum Entry {
+    Repo(Repo),
+    Folder(Folder),
+}
+
+impl Entry {
+    pub fn build_commands(&self) -> &[String] {
+        match self {
+            Entry::Repo(repo) => &repo.build,
+            Entry::Folder(folder) => &folder.build,
+        }
+    }
+
+    pub fn ecosystem(&self) -> EcosystemKind {
+        match self {
+            Entry::Repo(repo) => repo.ecosystem,
+            Entry::Folder(folder) => folder.ecosystem,
+        }
+    }
+
+    /// The name of the folder the project is retrieved into.
+    pub fn folder_name(&self) -> &str {
+        match self {
+            Entry::Repo(repo) => repo_folder(&repo.url),
+            Entry::Folder(folder) => folder
+                .name
+                .as_deref()
+                .unwrap_or_else(|| local_folder(&folder.path)),
+        }
+    }
+}
+
+/// A change of an entry from the command line, the entry being named after
+/// the folder it is retrieved into.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Override {
+    /// Check out another branch of a repository
+    Branch { name: String, branch: String },
+    /// Copy the project from a local folder instead
+    Path { name: String, path: String },
+}
+
+impl fmt::Display for Override {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Override::Branch { name, branch } => write!(f, "{}: branch {}", name, branch),
+            Override::Path { name, path } => write!(f, "{}: path {}", name, path),
+        }
+    }
+}
+
+fn default_attempts() -> u32 {
+    1
+}
+
+/// How many times a step is attempted before giving up.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+    /// Delay before the first retry in milliseconds, doubled at each new attempt
+    #[serde(default)]
+    pub backoff_ms: u64,
+}
+
+// Network bound steps are retried by default
+fn default_network_retry() -> RetryPolicy {
+    RetryPolicy {
+        attempts: 3,
+        backoff_ms: 2000,
+    }
+}
+
+pub(crate) fn default_build_retry() -> RetryPolicy {
+    RetryPolicy {
+        attempts: 1,
+        backoff_ms: 0,
+    }
+}
+
+/// Retry policy per kind of step. Network bound steps (clone, install and
+/// publish) are retried 3 times by default, build commands fail fast.
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct Retries {
+    #[serde(default = "default_network_retry")]
+    pub clone: RetryPolicy,
+    #[serde(default = "default_network_retry")]
+    pub install: RetryPolicy,
+    #[serde(default = "default_build_retry")]
+    pub build: RetryPolicy,
+    #[serde(default = "default_network_retry")]
+    pub publish: RetryPolicy,
+}
+
+impl Default for Retries {
+    fn default() -> Self {
+        Retries {
+            clone: default_network_retry(),
+            install: default_network_retry(),
+            build: default_build_retry(),
+            publish: default_network_retry(),
+        }
+    }
+}
+
+/// Timeouts in seconds per kind of step, commands are never killed by default.
+#[derive(Deserialize, JsonSchema, Debug, Default)]
+pub struct Timeouts {
+    pub clone: Option<u64>,
+    pub install: Option<u64>,
+    pub build: Option<u64>,
+    pub pack: Option<u64>,
+    pub publish: Option<u64>,
+}
+
+pub(crate) fn seconds(timeout: Option<u64>) -> Option<Duration> {
+    timeout.map(Duration::from_secs)
+}
+
+/// What happens to the lockfiles of the projects.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Lockfile {
+    /// Deleted, the dependencies are resolved again
+    #[default]
+    Discard,
+    /// Only the entries of the integrated packages are updated, the other
+    /// dependencies are installed as locked
+    Preserve,
+}
+
+/// How the dependents of a published package install it.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Link {
+    /// From the artifact built for it
+    #[default]
+    File,
+    /// From the registry, at the exact version published
+    Registry,
+}
+
+pub(crate) fn default_dist_tag() -> String {
+    String::from("integ")
+}
+
+/// A registry the artifacts are published to once built.
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct Publish {
+    pub registry: String,
+    /// The dist-tag the packages are published under
+    #[serde(default = "default_dist_tag")]
+    pub tag: String,
+    #[serde(default)]
+    pub link: Link,
+}
+
+/// The content of an integ configuration file.
+#[derive(Deserialize, JsonSchema, Debug)]
+pub struct Config {
+    /// Configuration files merged under this one, relative to it. Their lists
+    /// are prepended to the ones of this file.
+    #[serde(default)]
+    pub include: Vec<PathBuf>,
+    /// Values substituted to `${name}` in the configuration, the environment
+    /// variables being used for the other names
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    /// Named lists of build commands the entries can start with
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Vec<String>>,
+    pub repositories: Vec<Entry>,
+    /// Parallel builds are not implemented yet
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    /// Folder where bare mirrors of the repositories are kept between runs
+    pub cache: Option<PathBuf>,
+    #[serde(default)]
+    pub retry: Retries,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    /// The kinds of dependencies which order the builds and get patched
+    #[serde(default = "default_edges")]
+    pub edges: Vec<DependencyKind>,
+    #[serde(default)]
+    pub version_check: VersionCheck,
+    /// Rewrite the version of each built project to a prerelease identifying
+    /// the run and the commit
+    #[serde(default)]
+    pub stamp: bool,
+    pub publish: Option<Publish>,
+    #[serde(default)]
+    pub lockfile: Lockfile,
+}
+
+impl Config {
+    /// Apply a change made from the command line to the entry it names.
+    pub fn apply(&mut self, change: &Override) -> Result<()> {
+        let name = match change {
+            Override::Branch { name, .. } | Override::Path { name, .. } => name,
+        };
+        let entry = match self
+            .repositories
+            .iter_mut()
+            .find(|entry| entry.folder_name() == name)
+        {
+            Some(entry) => entry,
+            None => {
+                let names = self.repositories.iter().map(Entry::folder_name);
+                let hint = match suggest(name, names) {
+                    Some(suggestion) => format!(", did you mean `{}`?", suggestion),
+                    None => String::new(),
+                };
+                return Err(anyhow::anyhow!("no entry named `{}`{}", name, hint));
+            }
+        };
+        match (change, entry) {
+            (Override::Branch { branch, .. }, Entry::Repo(repo)) => repo.branch = branch.clone(),
+            (Override::Branch { .. }, Entry::Folder(_)) => {
+                return Err(anyhow::anyhow!(
+                    "`{}` is a local folder, it has no branch",
+                    name
+                ))
+            }
+            (Override::Path { path, .. }, entry) => {
+                let (build, profile, ecosystem) = match entry {
+                    Entry::Repo(repo) => (repo.build.clone(), repo.profile.clone(), repo.ecosystem),
+                    Entry::Folder(folder) => (
+                        folder.build.clone(),
+                        folder.profile.clone(),
+                        folder.ecosystem,
+                    ),
+                };
+                // Still named and retrieved as the entry it replaces
+                *entry = Entry::Folder(Folder {
+                    path: path.clone(),
+                    build,
+                    profile,
+                    ecosystem,
+                    name: Some(name.clone()),
+                });
+            }
+        }
+        let mut folders = vec![];
+        for entry in &self.repositories {
+            if folders.contains(&entry.folder_name()) {
+                return Err(anyhow::anyhow!(
+                    "`{}` would be retrieved by two entries",
+                    entry.folder_name()
+                ));
+            }
+            folders.push(entry.folder_name());
+        }
+        Ok(())
+    }
+}
+
+/// The language a configuration file is written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl Format {
+    /// The format of a file from its extension, YAML if it is not `.json` or
+    /// `.toml`.
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Format::Json,
+            Some("toml") => Format::Toml,
+            _ => Format::Yaml,
+        }
+    }
+
+    /// Parse a document of this format, before its includes, variables and
+    /// profiles are resolved.
+    pub(crate) fn parse(self, document: &str) -> Result<serde_yaml::Value> {
+        Ok(match self {
+            Format::Yaml => serde_yaml::from_str(document)?,
+            Format::Json => serde_json::from_str(document)?,
+            Format::Toml => toml_edit::de::from_str(document)?,
+        })
+    }
+}
+
+fn resolve_config(config: &str, format: Format, base: &Path) -> Result<Config> {
+    let document = format.parse(config)?;
+    let env = std::env::vars().collect();
+    let document = crate::preprocess::resolve(document, base, &env)?;
+    Ok(serde_yaml::from_value(document)?)
+}
+
+/// Parse a YAML configuration, including the files relative to the current
+/// directory.
+pub fn load_config(config_yaml: &str) -> Result<Config> {
+    resolve_config(config_yaml, Format::Yaml, Path::new("."))
+}
+
+/// Read a YAML, JSON or TOML configuration file, as per its extension,
+/// including the files relative to it.
+pub fn load_config_file(path: &Path) -> Result<Config> {
+    let config = std::fs::read_to_string(path)
+        .with_context(|| format!("could not read file `{}`", path.to_string_lossy()))?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    resolve_config(&config, Format::from_path(path), base)
+        .with_context(|| format!("invalid configuration `{}`", path.to_string_lossy()))
+}
+
+/// The JSON Schema of the configuration file, for editors to complete and
+/// check it.
+pub fn json_schema() -> String {
+    let schema = schemars::schema_for!(Config);
+    serde_json::to_string_pretty(&schema).unwrap() + "\n"
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_repo_options() {
+        let config = load_config(
+            r#"
+repositories:
+  - url: http://mygit.com/user/project-A
+    branch: master
+    build: []
+  - url: http://mygit.com/user/project-B
+    branch: master
+    build: []
+    submodules: true
+    lfs: true
+  - url: http://mygit.com/user/project-C
+    branch: master
+    build: []
+    submodules: recursive
+"#,
+        )
+        .unwrap();
+        let options = config
+            .repositories
+            .iter()
+            .map(|entry| match entry {
+                Entry::Repo(repo) => (repo.submodules, repo.lfs),
+                Entry::Folder(_) => panic!("expected a repository"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            options,
+            vec![
+                (Submodules::Disabled, false),
+                (Submodules::Enabled, true),
+                (Submodules::Recursive, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_retry_config() {
+        let config = load_config(
+            r#"
+repositories: []
+retry:
+  clone:
+    attempts: 5
+    backoff_ms: 100
+  build:
+    attempts: 2
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.retry.clone.attempts, 5);
+        assert_eq!(config.retry.clone.backoff_ms, 100);
+        assert_eq!(config.retry.install.attempts, 3);
+        assert_eq!(config.retry.build.attempts, 2);
+        assert_eq!(config.retry.build.backoff_ms, 0);
+    }
+
+    #[test]
+    fn test_json_schema() {
+        // Regenerate with `integ schema > integ.schema.json`
+        assert_eq!(json_schema(), include_str!("../integ.schema.json"));
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut config = load_config(
+            r#"
+repositories:
+  - url: http://mygit.com/user/project-A.git
+    branch: master
+    build: [npm run build]
+  - url: http://mygit.com/user/project-B
+    branch: master
+    build: [npm test]
+    ecosystem: cargo
+"#,
+        )
+        .unwrap();
+        config
+            .apply(&Override::Branch {
+                name: String::from("project-A"),
+                branch: String::from("fix-issue"),
+            })
+            .unwrap();
+        let local = Override::Path {
+            name: String::from("project-B"),
+            path: String::from("/home/me/b-fork"),
+        };
+        config.apply(&local).unwrap();
+        assert_eq!(local.to_string(), "project-B: path /home/me/b-fork");
+        match &config.repositories[..] {
+            [Entry::Repo(a), Entry::Folder(b)] => {
+                assert_eq!(a.branch, "fix-issue");
+                assert_eq!(b.path, "/home/me/b-fork");
+                assert_eq!(config.repositories[1].folder_name(), "project-B");
+                assert_eq!(b.build, vec!["npm test"]);
+                assert_eq!(b.ecosystem, EcosystemKind::Cargo);
+            }
+            entries => panic!("unexpected entries {:?}", entries),
+        }
+
+        let error = config
+            .apply(&Override::Branch {
+                name: String::from("project-b"),
+                branch: String::from("main"),
+            })
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "no entry named `project-b`, did you mean `project-B`?"
+        );
+
+        // Loaded without being validated
+        let mut config = load_config(
+            r#"
+repositories:
+  - { url: http://mygit.com/user/project-A, branch: master, build: [] }
+  - { url: http://mygit.com/other/project-A, branch: master, build: [] }
+"#,
+        )
+        .unwrap();
+        let error = config
+            .apply(&Override::Branch {
+                name: String::from("project-A"),
+                branch: String::from("main"),
+            })
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`project-A` would be retrieved by two entries"
+        );
+    }
+
+    #[test]
+    fn test_formats() {
+        let root = tempfile::tempdir().unwrap();
+        let files = [
+            (
+                "integ.yaml",
+                "repositories:\n  - { path: /a, build: [make], ecosystem: cargo }\nstamp: true\n",
+            ),
+            (
+                "integ.json",
+                r#"{"repositories": [{"path": "/a", "build": ["make"], "ecosystem": "cargo"}], "stamp": true}"#,
+            ),
+            (
+                "integ.toml",
+                "stamp = true\n[[repositories]]\npath = \"/a\"\nbuild = [\"make\"]\necosystem = \"cargo\"\n",
+            ),
+        ];
+        for (name, content) in files {
+            let path = root.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            let config = load_config_file(&path).unwrap();
+            assert!(config.stamp, "{}", name);
+            assert_eq!(config.repositories[0].folder_name(), "a");
+            assert_eq!(config.repositories[0].build_commands(), ["make"]);
+            assert_eq!(config.repositories[0].ecosystem(), EcosystemKind::Cargo);
+        }
+    }
+}
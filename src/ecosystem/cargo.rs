@@ -0,0 +1,373 @@
+//! Rust projects described by a `Cargo.toml`.
+//!
+//! The dependencies on the other integrated crates are rewritten to `path`
+//! dependencies pointing at their checkout, so the artifact of a crate is a
+//! stamp file at the root of its folder recording when it was last built.
+use super::{prerelease, Dependency, DependencyKind, Ecosystem, Manifest};
+use crate::command::{self, Requirement};
+use crate::event::say;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use toml_edit::{DocumentMut, InlineTable, Item, Table, TableLike, Value};
+
+const DEPENDENCY_TABLES: [(&str, DependencyKind); 3] = [
+    ("dependencies", DependencyKind::Normal),
+    ("dev-dependencies", DependencyKind::Dev),
+    ("build-dependencies", DependencyKind::Build),
+];
+// Keys selecting where a dependency comes from, replaced by `path`
+const SOURCE_KEYS: [&str; 5] = ["git", "branch", "tag", "rev", "registry"];
+const ARTIFACT: &str = "integ.artifact";
+
+pub struct Cargo;
+
+fn read_manifest(folder: &str) -> Result<DocumentMut> {
+    let cargo_toml_path = PathBuf::from(folder).join("Cargo.toml");
+    let cargo_toml = fs::read_to_string(&cargo_toml_path)
+        .with_context(|| format!("reading {}", cargo_toml_path.to_string_lossy()))?;
+    cargo_toml
+        .parse::<DocumentMut>()
+        .with_context(|| format!("Trying to parse {}", cargo_toml_path.to_string_lossy()))
+}
+
+// The [dependencies], [dev-dependencies] and [build-dependencies] tables,
+// including the platform specific ones under [target.'cfg(...)']
+fn dependency_tables(table: &mut Table) -> Vec<(DependencyKind, &mut dyn TableLike)> {
+    let mut tables = vec![];
+    for (key, item) in table.iter_mut() {
+        if let Some((_, kind)) = DEPENDENCY_TABLES
+            .iter()
+            .find(|(name, _)| *name == key.get())
+        {
+            if let Some(dependencies) = item.as_table_like_mut() {
+                tables.push((*kind, dependencies));
+            }
+        } else if key.get() == "target" {
+            if let Some(targets) = item.as_table_mut() {
+                for (_, target) in targets.iter_mut() {
+                    if let Some(target) = target.as_table_mut() {
+                        tables.extend(dependency_tables(target));
+                    }
+                }
+            }
+        }
+    }
+    tables
+}
+
+// The name of the crate a dependency refers to, which differs from its key
+// when the dependency is renamed with `package = "..."`
+fn crate_name(key: &str, item: &Item) -> String {
+    item.get("package")
+        .and_then(|package| package.as_str())
+        .unwrap_or(key)
+        .to_string()
+}
+
+fn write_manifest(folder: &str, document: &DocumentMut) -> Result<()> {
+    let cargo_toml_path = PathBuf::from(folder).join("Cargo.toml");
+    fs::write(&cargo_toml_path, document.to_string())
+        .with_context(|| format!("Trying to patch {}", cargo_toml_path.to_string_lossy()))?;
+    Ok(())
+}
+
+fn patch_dependency(item: &mut Item, path: &str) {
+    if let Some(version) = item.as_str().map(String::from) {
+        let mut dependency = InlineTable::new();
+        dependency.insert("version", version.into());
+        dependency.insert("path", path.into());
+        *item = Item::Value(Value::InlineTable(dependency));
+    } else if let Some(dependency) = item.as_table_like_mut() {
+        for key in SOURCE_KEYS {
+            dependency.remove(key);
+        }
+        dependency.insert("path", toml_edit::value(path));
+    }
+}
+
+impl Ecosystem for Cargo {
+    fn name(&self) -> &'static str {
+        "cargo"
+    }
+
+    fn requirements(&self) -> Vec<Requirement> {
+        vec![Requirement {
+            tool: "cargo",
+            args: &["--version"],
+            min_version: Some((1, 40, 0)),
+            reason: String::from("to fetch the dependencies of Rust projects"),
+        }]
+    }
+
+    fn manifest(&self, folder: &str) -> Result<Manifest> {
+        let mut document = read_manifest(folder)?;
+        let package = document
+            .get("package")
+            .with_context(|| format!("no [package] in {}/Cargo.toml", folder))?;
+        let name = package
+            .get("name")
+            .and_then(|name| name.as_str())
+            .with_context(|| format!("no package name in {}/Cargo.toml", folder))?
+            .to_string();
+        // Not a string when inherited from a workspace
+        let version = package
+            .get("version")
+            .and_then(|version| version.as_str())
+            .map(String::from);
+        let dependencies = dependency_tables(document.as_table_mut())
+            .into_iter()
+            .flat_map(|(kind, table)| {
+                table
+                    .iter()
+                    .map(|(key, item)| Dependency {
+                        name: crate_name(key, item),
+                        requirement: item
+                            .as_str()
+                            .or_else(|| item.get("version").and_then(|v| v.as_str()))
+                            .unwrap_or("*")
+                            .to_string(),
+                        kind,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        Ok(Manifest {
+            name,
+            version,
+            dependencies,
+        })
+    }
+
+    fn patch(&self, folder: &str, artifacts: &[(String, String)]) -> Result<()> {
+        if artifacts.is_empty() {
+            return Ok(());
+        }
+        // The artifact of a crate lies at the root of its folder
+        let paths = artifacts
+            .iter()
+            .map(|(name, artifact)| {
+                let crate_folder = Path::new(artifact)
+                    .parent()
+                    .with_context(|| format!("no folder for the artifact {}", artifact))?;
+                let relative_path =
+                    pathdiff::diff_paths(crate_folder, folder).with_context(|| {
+                        format!(
+                            "Trying to find {} relatively to {}",
+                            crate_folder.to_string_lossy(),
+                            folder
+                        )
+                    })?;
+                Ok((name.as_str(), relative_path.to_string_lossy().to_string()))
+            })
+            .collect::<Result<HashMap<&str, String>>>()?;
+        let mut document = read_manifest(folder)?;
+        for (_, table) in dependency_tables(document.as_table_mut()) {
+            for (key, item) in table.iter_mut() {
+                if let Some(path) = paths.get(crate_name(key.get(), item).as_str()) {
+                    patch_dependency(item, path);
+                }
+            }
+        }
+        write_manifest(folder, &document)
+    }
+
+    fn pin_versions(&self, folder: &str, versions: &[(String, String)]) -> Result<()> {
+        // Cargo checks the version of a path dependency against its range,
+        // which only accepts a prerelease when it names it
+        let mut document = read_manifest(folder)?;
+        for (_, table) in dependency_tables(document.as_table_mut()) {
+            for (key, item) in table.iter_mut() {
+                let name = crate_name(key.get(), item);
+                let version = versions.iter().find(|(known, _)| *known == name);
+                if let (Some((_, version)), Some(dependency)) = (version, item.as_table_like_mut())
+                {
+                    if dependency.contains_key("version") {
+                        dependency.insert("version", toml_edit::value(format!("={}", version)));
+                    }
+                }
+            }
+        }
+        write_manifest(folder, &document)
+    }
+
+    fn stamp_version(&self, folder: &str, stamp: &str) -> Result<String> {
+        let mut document = read_manifest(folder)?;
+        let package = document
+            .get_mut("package")
+            .and_then(|package| package.as_table_like_mut())
+            .with_context(|| format!("no [package] in {}/Cargo.toml", folder))?;
+        let version = package
+            .get("version")
+            .and_then(|version| version.as_str())
+            .with_context(|| format!("no version in {}/Cargo.toml", folder))?;
+        let stamped = prerelease(version, stamp);
+        package.insert("version", toml_edit::value(stamped.as_str()));
+        write_manifest(folder, &document)?;
+        Ok(stamped)
+    }
+
+    fn satisfies(&self, version: &str, requirement: &str) -> Option<bool> {
+        let version = semver::Version::parse(version).ok()?;
+        let requirement = semver::VersionReq::parse(requirement).ok()?;
+        Some(requirement.matches(&version))
+    }
+
+    fn install(&self, folder: &str, _locked: bool, timeout: Option<Duration>) -> Result<()> {
+        say!("Fetching dependencies for {}", folder);
+        command::run(
+            Command::new("cargo").current_dir(folder).arg("fetch"),
+            timeout,
+        )
+        .with_context(|| format!("Trying to fetch dependencies in {}", folder))?;
+        Ok(())
+    }
+
+    fn package(&self, folder: &str, _timeout: Option<Duration>) -> Result<String> {
+        let manifest = self.manifest(folder)?;
+        let artifact = PathBuf::from(folder).join(ARTIFACT);
+        fs::write(
+            &artifact,
+            format!(
+                "{} {}\n",
+                manifest.name,
+                manifest.version.as_deref().unwrap_or("")
+            ),
+        )
+        .with_context(|| format!("Trying to write {}", artifact.to_string_lossy()))?;
+        say!("{} built", manifest.name);
+        Ok(artifact.to_string_lossy().to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn create_crate(root: &Path, name: &str, cargo_toml: &str, lib_rs: &str) -> String {
+        let folder = root.join(name);
+        fs::create_dir_all(folder.join("src")).unwrap();
+        fs::write(folder.join("Cargo.toml"), cargo_toml).unwrap();
+        fs::write(folder.join("src").join("lib.rs"), lib_rs).unwrap();
+        folder.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_manifest_and_patch() {
+        let root = tempfile::tempdir().unwrap();
+        let a = create_crate(
+            root.path(),
+            "a",
+            "[package]\nname = \"a\"\nversion = \"0.1.0\"\nedition = \"2018\"\n",
+            "pub fn a() -> u32 { 42 }\n",
+        );
+        let b = create_crate(
+            root.path(),
+            "b",
+            r#"[package]
+name = "b"
+version = "0.2.0"
+edition = "2018"
+
+[dependencies]
+# Comments are kept
+a = "0.1"
+
+[dev-dependencies]
+renamed = { package = "a", git = "https://mygit.com/user/a", branch = "main" }
+
+[target.'cfg(unix)'.dependencies]
+libc = "0.2"
+"#,
+            "pub fn b() -> u32 { a::a() }\n",
+        );
+
+        let manifest = Cargo.manifest(&b).unwrap();
+        assert_eq!(manifest.name, "b");
+        assert_eq!(manifest.version.as_deref(), Some("0.2.0"));
+        assert_eq!(
+            manifest.dependencies,
+            vec![
+                Dependency {
+                    name: String::from("a"),
+                    requirement: String::from("0.1"),
+                    kind: DependencyKind::Normal,
+                },
+                Dependency {
+                    name: String::from("a"),
+                    requirement: String::from("*"),
+                    kind: DependencyKind::Dev,
+                },
+                Dependency {
+                    name: String::from("libc"),
+                    requirement: String::from("0.2"),
+                    kind: DependencyKind::Normal,
+                },
+            ]
+        );
+
+        let artifact = Cargo.package(&a, None).unwrap();
+        Cargo.patch(&b, &[(String::from("a"), artifact)]).unwrap();
+        let cargo_toml = fs::read_to_string(Path::new(&b).join("Cargo.toml")).unwrap();
+        assert!(cargo_toml.contains("# Comments are kept"));
+        assert!(cargo_toml.contains(r#"a = { version = "0.1", path = "../a" }"#));
+        assert!(cargo_toml.contains(r#"renamed = { package = "a", path = "../a" }"#));
+        assert!(cargo_toml.contains(r#"libc = "0.2""#));
+
+        // Prereleases are only accepted by the ranges naming them
+        let stamped = Cargo.stamp_version(&a, "integ.1.abc1234").unwrap();
+        assert_eq!(stamped, "0.1.0-integ.1.abc1234");
+        Cargo
+            .pin_versions(&b, &[(String::from("a"), stamped)])
+            .unwrap();
+        let cargo_toml = fs::read_to_string(Path::new(&b).join("Cargo.toml")).unwrap();
+        assert!(cargo_toml.contains(r#"a = { version = "=0.1.0-integ.1.abc1234", path = "../a" }"#));
+
+        // b now builds against the sibling checkout of a, fully offline
+        let cargo_toml = cargo_toml
+            .lines()
+            .filter(|line| !line.starts_with("renamed") && !line.starts_with("libc"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        fs::write(Path::new(&b).join("Cargo.toml"), cargo_toml).unwrap();
+        command::run(
+            Command::new("cargo")
+                .current_dir(&b)
+                .args(["build", "--offline", "--quiet"]),
+            None,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_dev_dependency_cycle() {
+        use crate::graph::{build_dependency_graph, topo_sort};
+        // a tests itself through b, which depends on a
+        let root = tempfile::tempdir().unwrap();
+        let a = create_crate(
+            root.path(),
+            "a",
+            "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dev-dependencies]\nb = \"0.1\"\n",
+            "",
+        );
+        let b = create_crate(
+            root.path(),
+            "b",
+            "[package]\nname = \"b\"\nversion = \"0.1.0\"\n\n[dependencies]\na = \"0.1\"\n",
+            "",
+        );
+        let manifests = vec![Cargo.manifest(&a).unwrap(), Cargo.manifest(&b).unwrap()];
+
+        let graph =
+            build_dependency_graph(&manifests, &[DependencyKind::Normal, DependencyKind::Dev])
+                .unwrap();
+        let error = topo_sort(&graph).unwrap_err().to_string();
+        assert!(error.contains("a -> b -> a"), "{}", error);
+
+        let graph = build_dependency_graph(&manifests, &[DependencyKind::Normal]).unwrap();
+        assert_eq!(topo_sort(&graph).unwrap(), vec!["a", "b"]);
+    }
+}
@@ -0,0 +1,488 @@
+//! Surgical updates of the lockfiles of npm projects (package-lock.json v2
+//! and v3, yarn.lock v1 and pnpm-lock.yaml v6 and v9), so that only the
+//! entries of the integrated packages change and the third party packages
+//! stay at the versions they were locked to.
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use serde_yaml::{Mapping, Value as Yaml};
+
+pub(crate) const PACKAGE_LOCK: &str = "package-lock.json";
+pub(crate) const YARN_LOCK: &str = "yarn.lock";
+pub(crate) const PNPM_LOCK: &str = "pnpm-lock.yaml";
+
+/// An integrated package, as installed in its dependents from a tarball.
+pub(crate) struct Sibling {
+    pub name: String,
+    /// The spec of the dependency in the patched package.json, i.e. the path
+    /// to the tarball relative to the dependent
+    pub spec: String,
+    pub version: String,
+    /// The dependencies declared by the package.json of the sibling
+    pub dependencies: Map<String, Value>,
+}
+
+const SECTIONS: [&str; 4] = [
+    "dependencies",
+    "devDependencies",
+    "optionalDependencies",
+    "peerDependencies",
+];
+
+fn declares(package: &Map<String, Value>, section: &str, name: &str) -> bool {
+    package
+        .get(section)
+        .and_then(|dependencies| dependencies.get(name))
+        .is_some()
+}
+
+/// Update a package-lock.json to the patched `package`.
+pub(crate) fn update_package_lock(
+    lock: &mut Value,
+    package: &Map<String, Value>,
+    siblings: &[Sibling],
+) -> Result<()> {
+    let lockfile_version = lock["lockfileVersion"].as_u64().unwrap_or(1);
+    if lockfile_version < 2 {
+        return Err(anyhow::anyhow!(
+            "lockfileVersion {} is not supported, upgrade it with npm 7 or later",
+            lockfile_version
+        ));
+    }
+    let packages = lock["packages"]
+        .as_object_mut()
+        .context("no packages in the lockfile")?;
+    // npm ci checks that the root entry declares the same dependencies as the
+    // package.json
+    let root = packages
+        .entry("")
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()
+        .context("invalid root package in the lockfile")?;
+    for section in SECTIONS {
+        match package.get(section) {
+            Some(dependencies) => root.insert(section.to_string(), dependencies.clone()),
+            None => root.remove(section),
+        };
+    }
+    for sibling in siblings {
+        let installed_at = format!("node_modules/{}", sibling.name);
+        let mut found = false;
+        for (path, entry) in packages.iter_mut() {
+            if *path == installed_at || path.ends_with(&format!("/{}", installed_at)) {
+                update_package_entry(entry, sibling);
+                found = true;
+            }
+        }
+        // A peer dependency which was not installed so far
+        if !found {
+            let mut entry = Value::Object(Map::new());
+            update_package_entry(&mut entry, sibling);
+            let dev = !["dependencies", "optionalDependencies"]
+                .iter()
+                .any(|section| declares(package, section, &sibling.name));
+            if dev {
+                entry["dev"] = Value::Bool(true);
+            }
+            packages.insert(installed_at, entry);
+        }
+    }
+    // The tree of lockfile v1, kept in v2 for the older versions of npm
+    if let Some(Value::Object(dependencies)) = lock.get_mut("dependencies") {
+        for sibling in siblings {
+            let entry = dependencies
+                .entry(sibling.name.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+            entry["version"] = Value::from(format!("file:{}", sibling.spec));
+            let entry = entry.as_object_mut().unwrap();
+            entry.remove("resolved");
+            entry.remove("integrity");
+            if sibling.dependencies.is_empty() {
+                entry.remove("requires");
+            } else {
+                entry.insert(
+                    String::from("requires"),
+                    Value::Object(sibling.dependencies.clone()),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn update_package_entry(entry: &mut Value, sibling: &Sibling) {
+    entry["version"] = Value::from(sibling.version.as_str());
+    entry["resolved"] = Value::from(format!("file:{}", sibling.spec));
+    let entry = entry.as_object_mut().unwrap();
+    // The tarball changes with every build
+    entry.remove("integrity");
+    if sibling.dependencies.is_empty() {
+        entry.remove("dependencies");
+    } else {
+        entry.insert(
+            String::from("dependencies"),
+            Value::Object(sibling.dependencies.clone()),
+        );
+    }
+}
+
+fn yarn_quote(text: &str) -> String {
+    format!("\"{}\"", text)
+}
+
+// The patterns an entry of a yarn.lock resolves, e.g. `lodash@^4.17.0`
+fn yarn_patterns(block: &str) -> Vec<String> {
+    let header = block.lines().next().unwrap_or("");
+    if header.starts_with('#') || !header.ends_with(':') {
+        return vec![];
+    }
+    header
+        .trim_end_matches(':')
+        .split(", ")
+        .map(|pattern| pattern.trim_matches('"').to_string())
+        .collect()
+}
+
+// The name of the package a pattern resolves, the scope included
+fn yarn_pattern_name(pattern: &str) -> &str {
+    match pattern.get(1..).and_then(|rest| rest.find('@')) {
+        Some(at) => &pattern[..at + 1],
+        None => pattern,
+    }
+}
+
+/// Replace the entries of the siblings in a yarn.lock (v1) by the ones of
+/// their tarballs, leaving the entries of the third party packages as they
+/// are. The other patterns of a sibling (e.g. its range in the dependencies
+/// of another sibling) are resolved to the tarball too, so that a single copy
+/// of it is installed.
+pub(crate) fn update_yarn_lock(lock: &str, siblings: &[Sibling]) -> Result<String> {
+    if lock.contains("__metadata:") {
+        return Err(anyhow::anyhow!(
+            "the lockfiles of Yarn 2 and later are not supported"
+        ));
+    }
+    // Split the lockfile into blocks of one entry (or comment) each
+    let mut blocks: Vec<String> = vec![];
+    for line in lock.lines() {
+        if !line.starts_with(' ') && !line.is_empty() || blocks.is_empty() {
+            blocks.push(String::new());
+        }
+        let block = blocks.last_mut().unwrap();
+        block.push_str(line);
+        block.push('\n');
+    }
+    for sibling in siblings {
+        // The previous entries of the sibling are removed, the new one taking
+        // the place of the first
+        let mut patterns = vec![format!("{}@{}", sibling.name, sibling.spec)];
+        let mut position = None;
+        let mut separator = "";
+        let mut index = 0;
+        while index < blocks.len() {
+            let known = yarn_patterns(&blocks[index]);
+            if known.is_empty()
+                || known
+                    .iter()
+                    .any(|pattern| yarn_pattern_name(pattern) != sibling.name)
+            {
+                index += 1;
+                continue;
+            }
+            let block = blocks.remove(index);
+            if position.is_none() {
+                position = Some(index);
+                separator = if block.ends_with("\n\n") { "\n" } else { "" };
+            }
+            for pattern in known {
+                if !patterns.contains(&pattern) {
+                    patterns.push(pattern);
+                }
+            }
+        }
+        let header = patterns
+            .iter()
+            .map(|pattern| yarn_quote(pattern))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut entry = format!(
+            "{}:\n  version {}\n  resolved {}\n",
+            header,
+            yarn_quote(&sibling.version),
+            yarn_quote(&format!("file:{}", sibling.spec))
+        );
+        if !sibling.dependencies.is_empty() {
+            entry += "  dependencies:\n";
+            for (name, range) in &sibling.dependencies {
+                entry += &format!(
+                    "    {} {}\n",
+                    yarn_quote(name),
+                    yarn_quote(range.as_str().unwrap_or("*"))
+                );
+            }
+        }
+        match position {
+            Some(index) => blocks.insert(index, entry + separator),
+            None => {
+                if !blocks.last().is_some_and(|block| block.ends_with("\n\n")) {
+                    blocks.push(String::from("\n"));
+                }
+                blocks.push(entry);
+            }
+        }
+    }
+    Ok(blocks.concat())
+}
+
+// The mapping at `key` in `mapping`, created if missing
+fn yaml_mapping<'a>(mapping: &'a mut Mapping, key: &str) -> Result<&'a mut Mapping> {
+    let yaml_key = Yaml::from(key);
+    if !mapping.contains_key(&yaml_key) {
+        mapping.insert(yaml_key.clone(), Yaml::Mapping(Mapping::new()));
+    }
+    mapping
+        .get_mut(&yaml_key)
+        .unwrap()
+        .as_mapping_mut()
+        .with_context(|| format!("invalid {} in pnpm-lock.yaml", key))
+}
+
+fn yaml_get_mut<'a>(yaml: &'a mut Yaml, key: &str) -> Option<&'a mut Yaml> {
+    yaml.as_mapping_mut()?.get_mut(&Yaml::from(key))
+}
+
+/// Update a pnpm-lock.yaml (v6 or v9) to the patched `package`.
+pub(crate) fn update_pnpm_lock(
+    lock: &str,
+    package: &Map<String, Value>,
+    siblings: &[Sibling],
+) -> Result<String> {
+    let mut lock: Yaml = serde_yaml::from_str(lock).context("invalid pnpm-lock.yaml")?;
+    let lockfile_version = match &lock["lockfileVersion"] {
+        Yaml::String(version) => version.parse::<f64>().unwrap_or(0.0),
+        Yaml::Number(version) => version.as_f64().unwrap_or(0.0),
+        _ => 0.0,
+    };
+    if lockfile_version < 6.0 {
+        return Err(anyhow::anyhow!(
+            "pnpm-lock.yaml version {} is not supported, upgrade it with pnpm 8 or later",
+            lockfile_version
+        ));
+    }
+    let v9 = lockfile_version >= 9.0;
+    for sibling in siblings {
+        let reference = format!("file:{}", sibling.spec);
+        // The dependencies of the project itself, at the root of a v6
+        // lockfile or in the `.` importer
+        let importer = if lock["importers"]["."].is_mapping() {
+            yaml_get_mut(yaml_get_mut(&mut lock, "importers").unwrap(), ".").unwrap()
+        } else {
+            &mut lock
+        };
+        let importer = importer
+            .as_mapping_mut()
+            .context("invalid importer in pnpm-lock.yaml")?;
+        for section in ["dependencies", "devDependencies", "optionalDependencies"] {
+            if !declares(package, section, &sibling.name) {
+                continue;
+            }
+            let mut dependency = Mapping::new();
+            dependency.insert(Yaml::from("specifier"), Yaml::from(sibling.spec.as_str()));
+            dependency.insert(Yaml::from("version"), Yaml::from(reference.as_str()));
+            yaml_mapping(importer, section)?
+                .insert(Yaml::from(sibling.name.as_str()), Yaml::Mapping(dependency));
+        }
+
+        // The resolved dependencies are kept from the previous entry of the
+        // sibling, the third party ones being locked
+        let previous_key = |key: &Yaml| {
+            key.as_str().is_some_and(|key| {
+                let key = key.trim_start_matches('/');
+                key.starts_with(&format!("{}@", sibling.name))
+            })
+        };
+        let mut package_entry = Mapping::new();
+        let mut resolution = Mapping::new();
+        resolution.insert(Yaml::from("tarball"), Yaml::from(reference.as_str()));
+        package_entry.insert(Yaml::from("resolution"), Yaml::Mapping(resolution));
+        let key = if v9 {
+            format!("{}@{}", sibling.name, reference)
+        } else {
+            package_entry.insert(Yaml::from("name"), Yaml::from(sibling.name.as_str()));
+            reference.clone()
+        };
+        package_entry.insert(Yaml::from("version"), Yaml::from(sibling.version.as_str()));
+        let snapshots_section = if v9 { "snapshots" } else { "packages" };
+        let previous_dependencies = lock[snapshots_section]
+            .as_mapping()
+            .and_then(|entries| entries.iter().find(|(key, _)| previous_key(key)))
+            .and_then(|(_, entry)| entry.get("dependencies").cloned());
+        let lock = lock.as_mapping_mut().context("invalid pnpm-lock.yaml")?;
+        let packages = yaml_mapping(lock, "packages")?;
+        if v9 {
+            packages.insert(Yaml::from(key.as_str()), Yaml::Mapping(package_entry));
+            let mut snapshot = Mapping::new();
+            if let Some(dependencies) = previous_dependencies {
+                snapshot.insert(Yaml::from("dependencies"), dependencies);
+            }
+            yaml_mapping(lock, "snapshots")?
+                .insert(Yaml::from(key.as_str()), Yaml::Mapping(snapshot));
+        } else {
+            if let Some(dependencies) = previous_dependencies {
+                package_entry.insert(Yaml::from("dependencies"), dependencies);
+            }
+            packages.insert(Yaml::from(key.as_str()), Yaml::Mapping(package_entry));
+        }
+    }
+    let lock = serde_yaml::to_string(&lock)?;
+    // serde_yaml starts the document with a marker pnpm does not write
+    Ok(lock.trim_start_matches("---\n").to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn sibling() -> Sibling {
+        Sibling {
+            name: String::from("@x/a"),
+            spec: String::from("../a/x-a-1.1.0.tgz"),
+            version: String::from("1.1.0"),
+            dependencies: json!({ "lodash": "^4.17.0" }).as_object().unwrap().clone(),
+        }
+    }
+
+    #[test]
+    fn test_update_package_lock() {
+        let package = json!({
+            "name": "@x/c",
+            "dependencies": { "@x/a": "../a/x-a-1.1.0.tgz", "lodash": "^4.17.0" }
+        });
+        let mut lock = json!({
+            "name": "@x/c",
+            "lockfileVersion": 3,
+            "packages": {
+                "": { "name": "@x/c", "dependencies": { "@x/a": "^1.0.0", "lodash": "^4.17.0" } },
+                "node_modules/@x/a": {
+                    "version": "1.0.0",
+                    "resolved": "https://registry.npmjs.org/@x/a/-/a-1.0.0.tgz",
+                    "integrity": "sha512-old"
+                },
+                "node_modules/lodash": {
+                    "version": "4.17.21",
+                    "resolved": "https://registry.npmjs.org/lodash/-/lodash-4.17.21.tgz",
+                    "integrity": "sha512-lodash"
+                }
+            }
+        });
+        let before = lock["packages"]["node_modules/lodash"].clone();
+        update_package_lock(&mut lock, package.as_object().unwrap(), &[sibling()]).unwrap();
+        assert_eq!(
+            lock["packages"][""]["dependencies"]["@x/a"],
+            "../a/x-a-1.1.0.tgz"
+        );
+        assert_eq!(
+            lock["packages"]["node_modules/@x/a"],
+            json!({
+                "version": "1.1.0",
+                "resolved": "file:../a/x-a-1.1.0.tgz",
+                "dependencies": { "lodash": "^4.17.0" }
+            })
+        );
+        assert_eq!(lock["packages"]["node_modules/lodash"], before);
+
+        let mut lock = json!({ "lockfileVersion": 1, "dependencies": {} });
+        assert!(
+            update_package_lock(&mut lock, package.as_object().unwrap(), &[sibling()]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_update_yarn_lock() {
+        let lock = r#"# THIS IS AN AUTOGENERATED FILE. DO NOT EDIT THIS FILE DIRECTLY.
+# yarn lockfile v1
+
+
+"@x/a@^1.0.0":
+  version "1.0.0"
+  resolved "https://registry.yarnpkg.com/@x/a/-/a-1.0.0.tgz#abc"
+  integrity sha512-old
+
+lodash@^4.17.0:
+  version "4.17.21"
+  resolved "https://registry.yarnpkg.com/lodash/-/lodash-4.17.21.tgz#def"
+  integrity sha512-lodash
+"#;
+        let updated = update_yarn_lock(lock, &[sibling()]).unwrap();
+        // The old entry of the sibling is replaced, its pattern resolved to
+        // the tarball
+        assert!(!updated.contains("sha512-old"));
+        assert!(!updated.contains("version \"1.0.0\""));
+        assert_eq!(
+            updated,
+            r#"# THIS IS AN AUTOGENERATED FILE. DO NOT EDIT THIS FILE DIRECTLY.
+# yarn lockfile v1
+
+
+"@x/a@../a/x-a-1.1.0.tgz", "@x/a@^1.0.0":
+  version "1.1.0"
+  resolved "file:../a/x-a-1.1.0.tgz"
+  dependencies:
+    "lodash" "^4.17.0"
+
+lodash@^4.17.0:
+  version "4.17.21"
+  resolved "https://registry.yarnpkg.com/lodash/-/lodash-4.17.21.tgz#def"
+  integrity sha512-lodash
+"#
+        );
+        // Updating again gives the same lockfile
+        assert_eq!(update_yarn_lock(&updated, &[sibling()]).unwrap(), updated);
+        // A sibling which was not installed so far is appended
+        let lock = "lodash@^4.17.0:\n  version \"4.17.21\"\n";
+        let updated = update_yarn_lock(lock, &[sibling()]).unwrap();
+        assert!(updated.starts_with(lock));
+        assert!(updated.ends_with("\n\n\"@x/a@../a/x-a-1.1.0.tgz\":\n  version \"1.1.0\"\n  resolved \"file:../a/x-a-1.1.0.tgz\"\n  dependencies:\n    \"lodash\" \"^4.17.0\"\n"));
+    }
+
+    #[test]
+    fn test_update_pnpm_lock() {
+        let lock = r#"lockfileVersion: '9.0'
+importers:
+  .:
+    dependencies:
+      '@x/a':
+        specifier: ^1.0.0
+        version: 1.0.0
+packages:
+  '@x/a@1.0.0':
+    resolution: {integrity: sha512-old}
+  lodash@4.17.21:
+    resolution: {integrity: sha512-lodash}
+snapshots:
+  '@x/a@1.0.0':
+    dependencies:
+      lodash: 4.17.21
+  lodash@4.17.21: {}
+"#;
+        let package = json!({ "dependencies": { "@x/a": "../a/x-a-1.1.0.tgz" } });
+        let updated = update_pnpm_lock(lock, package.as_object().unwrap(), &[sibling()]).unwrap();
+        let updated: Yaml = serde_yaml::from_str(&updated).unwrap();
+        let dependency = &updated["importers"]["."]["dependencies"]["@x/a"];
+        assert_eq!(dependency["specifier"], Yaml::from("../a/x-a-1.1.0.tgz"));
+        assert_eq!(dependency["version"], Yaml::from("file:../a/x-a-1.1.0.tgz"));
+        let key = "@x/a@file:../a/x-a-1.1.0.tgz";
+        assert_eq!(
+            updated["packages"][key]["resolution"]["tarball"],
+            Yaml::from("file:../a/x-a-1.1.0.tgz")
+        );
+        assert_eq!(
+            updated["snapshots"][key]["dependencies"]["lodash"],
+            Yaml::from("4.17.21")
+        );
+        assert_eq!(
+            updated["packages"]["lodash@4.17.21"]["resolution"]["integrity"],
+            Yaml::from("sha512-lodash")
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Support for the package managers of the integrated projects.
+//!
+//! An [`Ecosystem`] knows how to read the manifest of a project, point its
+//! dependencies to the artifacts built for the other integrated projects,
+//! install its dependencies and package it.
+use crate::command::Requirement;
+pub use crate::config::DependencyKind;
+use crate::config::EcosystemKind;
+use anyhow::Result;
+use std::time::Duration;
+
+pub mod cargo;
+mod lockfile;
+pub mod npm;
+pub mod python;
+
+/// A dependency declared in a manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dependency {
+    pub name: String,
+    /// As declared in the manifest (version range, path, url...)
+    pub requirement: String,
+    pub kind: DependencyKind,
+}
+
+/// What integ needs to know about the manifest of a project.
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub name: String,
+    pub version: Option<String>,
+    pub dependencies: Vec<Dependency>,
+}
+
+pub trait Ecosystem {
+    fn name(&self) -> &'static str;
+
+    /// The tools this ecosystem needs, checked before anything is cloned.
+    fn requirements(&self) -> Vec<Requirement>;
+
+    /// Read the manifest of the project in `folder`.
+    fn manifest(&self, folder: &str) -> Result<Manifest>;
+
+    /// Make the dependencies of the project in `folder` point to the artifacts
+    /// built for the other integrated projects, given as (name, path) pairs.
+    /// Only the dependencies of the kinds configured as graph edges are given.
+    fn patch(&self, folder: &str, artifacts: &[(String, String)]) -> Result<()>;
+
+    /// Make the ranges declared on the integrated dependencies of the project
+    /// in `folder` accept their stamped versions, given as (name, version)
+    /// pairs. Called after `patch` when the versions are stamped.
+    fn pin_versions(&self, _folder: &str, _versions: &[(String, String)]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Rewrite the version of the project in `folder` to a prerelease of it
+    /// identified by `stamp` (e.g. `integ.42.1a2b3c4`) and return it. A stamp
+    /// from a previous run is replaced.
+    fn stamp_version(&self, folder: &str, _stamp: &str) -> Result<String> {
+        Err(anyhow::anyhow!(
+            "cannot stamp the version of {}, not supported by {}",
+            folder,
+            self.name()
+        ))
+    }
+
+    /// Make the dependencies of the project in `folder` on the other
+    /// integrated projects require their exact versions, given as (name,
+    /// version) pairs, and install them from `registry`. Replaces `patch`
+    /// when the packages are published and linked through the registry.
+    fn link_registry(
+        &self,
+        folder: &str,
+        _versions: &[(String, String)],
+        _registry: &str,
+    ) -> Result<()> {
+        Err(anyhow::anyhow!(
+            "cannot install the dependencies of {} from a registry, not supported by {}",
+            folder,
+            self.name()
+        ))
+    }
+
+    /// Whether `version` is in the range `requirement` as declared in a
+    /// manifest of this ecosystem, None if the requirement is not a range
+    /// (a path, an url...) or cannot be understood.
+    fn satisfies(&self, _version: &str, _requirement: &str) -> Option<bool> {
+        None
+    }
+
+    /// Update the entries of the integrated dependencies, given as (name,
+    /// path) pairs like for `patch`, in the lockfile of the project in
+    /// `folder`, leaving the other entries untouched. Returns false if the
+    /// project has no lockfile this ecosystem knows how to update.
+    fn update_lockfile(&self, _folder: &str, _artifacts: &[(String, String)]) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// The tools installing the dependencies of the project in `folder` from
+    /// its lockfile, besides the ones of the ecosystem.
+    fn lockfile_requirements(&self, _folder: &str) -> Vec<Requirement> {
+        vec![]
+    }
+
+    /// Install the dependencies of the project in `folder`, exactly as
+    /// locked and failing if the lockfile is out of date when `locked`.
+    /// Called again on failure as per the install retry policy.
+    fn install(&self, folder: &str, locked: bool, timeout: Option<Duration>) -> Result<()>;
+
+    /// The folders, relative to a project, its dependencies are installed
+    /// into, deleted by `integ clean --level dependencies`.
+    fn dependency_folders(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Environment variables set for the build commands of the project in
+    /// `folder`, e.g. to activate a virtualenv.
+    fn environment(&self, _folder: &str) -> Vec<(String, String)> {
+        vec![]
+    }
+
+    /// Package the built project in `folder` and return the path to the
+    /// artifact to install in its dependents.
+    fn package(&self, folder: &str, timeout: Option<Duration>) -> Result<String>;
+
+    /// Publish the `artifact` of the project in `folder` to `registry` under
+    /// the dist-tag `tag`. Called again on failure as per the publish retry
+    /// policy.
+    fn publish(
+        &self,
+        folder: &str,
+        _artifact: &str,
+        _registry: &str,
+        _tag: &str,
+        _timeout: Option<Duration>,
+    ) -> Result<()> {
+        Err(anyhow::anyhow!(
+            "cannot publish {}, not supported by {}",
+            folder,
+            self.name()
+        ))
+    }
+}
+
+/// The prerelease `version-stamp` of a version, replacing the stamp of a
+/// previous run if any.
+pub(crate) fn prerelease(version: &str, stamp: &str) -> String {
+    let release = version
+        .split_once("-integ.")
+        .map_or(version, |(release, _)| release);
+    format!("{}-{}", release, stamp)
+}
+
+/// The implementation of an ecosystem selected in the configuration.
+pub fn ecosystem(kind: EcosystemKind) -> &'static dyn Ecosystem {
+    match kind {
+        EcosystemKind::Npm => &npm::Npm,
+        EcosystemKind::Cargo => &cargo::Cargo,
+        EcosystemKind::Python => &python::Python,
+    }
+}
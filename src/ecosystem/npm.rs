@@ -0,0 +1,639 @@
+//! Projects managed with npm and described by a `package.json`.
+use super::lockfile::{self, Sibling, PACKAGE_LOCK, PNPM_LOCK, YARN_LOCK};
+use super::{prerelease, Dependency, DependencyKind, Ecosystem, Manifest};
+use crate::command::{self, Requirement};
+use crate::event::say;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+// The package manager and its arguments installing the dependencies of the
+// project in `folder` exactly as in its lockfile, if it has one
+fn locked_install(folder: &Path) -> Option<(&'static str, &'static [&'static str])> {
+    if folder.join(PACKAGE_LOCK).exists() {
+        Some(("npm", &["ci"]))
+    } else if folder.join(PNPM_LOCK).exists() {
+        Some(("pnpm", &["install", "--frozen-lockfile"]))
+    } else if folder.join(YARN_LOCK).exists() {
+        Some(("yarn", &["install", "--frozen-lockfile"]))
+    } else {
+        None
+    }
+}
+
+/// The parts of a `package.json` integ cares about.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Package {
+    pub name: String,
+    pub version: Option<String>,
+    #[serde(default)]
+    pub dependencies: HashMap<String, String>,
+    #[serde(default, rename = "devDependencies")]
+    pub dev_dependencies: HashMap<String, String>,
+    #[serde(default, rename = "peerDependencies")]
+    pub peer_dependencies: HashMap<String, String>,
+    #[serde(default, rename = "optionalDependencies")]
+    pub optional_dependencies: HashMap<String, String>,
+}
+
+// The sections of a package.json where a dependency can be installed from a
+// tarball. Peer dependencies only declare a compatible range.
+const INSTALLED_SECTIONS: [&str; 3] = ["dependencies", "devDependencies", "optionalDependencies"];
+
+pub struct Npm;
+
+// Whether a dependency is declared by a version range, rather than by a path,
+// a url or an alias
+fn is_range(requirement: &str) -> bool {
+    !requirement.contains(['/', '\\', ':']) && !requirement.ends_with(".tgz")
+}
+
+// Install `dependency` from `source` (a tarball or a version) wherever it is
+// installed from
+fn install_from(package: &mut Map<String, Value>, dependency: &str, source: Value) -> Result<()> {
+    let mut installed = false;
+    for section in INSTALLED_SECTIONS {
+        if let Some(Value::Object(dependencies)) = package.get_mut(section) {
+            if let Some(requirement) = dependencies.get_mut(dependency) {
+                *requirement = source.clone();
+                installed = true;
+            }
+        }
+    }
+    // A peer dependency is provided by the dependent package, install it next
+    // to it for the build and the tests
+    if !installed {
+        package
+            .entry("devDependencies")
+            .or_insert_with(|| Value::Object(Default::default()))
+            .as_object_mut()
+            .context("devDependencies is not an object")?
+            .insert(dependency.to_string(), source);
+    }
+    Ok(())
+}
+
+fn read_json(path: &Path) -> Result<Map<String, Value>> {
+    let json =
+        fs::read_to_string(path).with_context(|| format!("reading {}", path.to_string_lossy()))?;
+    match serde_json::from_str(&json)
+        .with_context(|| format!("Trying to parse {}", path.to_string_lossy()))?
+    {
+        Value::Object(object) => Ok(object),
+        _ => Err(anyhow::anyhow!(
+            "{} is not an object",
+            path.to_string_lossy()
+        )),
+    }
+}
+
+fn write_json(path: &Path, object: Map<String, Value>) -> Result<()> {
+    let json = serde_json::to_string_pretty(&Value::Object(object))? + "\n";
+    fs::write(path, json).with_context(|| format!("Trying to patch {}", path.to_string_lossy()))
+}
+
+// Edit the package.json of `folder` as a whole, to keep the fields integ does
+// not know about and their order
+fn edit_package_json(
+    folder: &str,
+    edit: impl FnOnce(&mut Map<String, Value>) -> Result<()>,
+) -> Result<()> {
+    let package_json_path = PathBuf::from(folder).join("package.json");
+    let mut package = read_json(&package_json_path)?;
+    edit(&mut package)?;
+    write_json(&package_json_path, package)
+}
+
+// The path to `path` from `folder`
+fn relative_path(path: &str, folder: &str) -> Result<PathBuf> {
+    pathdiff::diff_paths(path, folder)
+        .with_context(|| format!("Trying to find {} relatively to {}", path, folder))
+}
+
+// How an artifact is installed in the dependent in `folder`
+fn sibling(folder: &str, name: &str, tarball: &str) -> Result<Sibling> {
+    let relative_package_path = relative_path(tarball, folder)?;
+    // The tarball is packed in the folder of the sibling
+    let package = read_json(&Path::new(tarball).with_file_name("package.json"))?;
+    Ok(Sibling {
+        name: name.to_string(),
+        spec: relative_package_path.to_string_lossy().to_string(),
+        version: package
+            .get("version")
+            .and_then(|version| version.as_str())
+            .unwrap_or_default()
+            .to_string(),
+        dependencies: match package.get("dependencies") {
+            Some(Value::Object(dependencies)) => dependencies.clone(),
+            _ => Map::new(),
+        },
+    })
+}
+
+// Translate a set of npm comparators (`>= 1.2.0 <2`, `1.x`, `1.0.0 - 2.0.0`)
+// to the syntax of the semver crate, where a bare version means `^`
+fn comparators(range: &str) -> String {
+    if let Some((low, high)) = range.split_once(" - ") {
+        return format!(">={}, <={}", low.trim(), high.trim());
+    }
+    let mut comparators: Vec<String> = vec![];
+    let mut operator = String::new();
+    for token in range.split_whitespace() {
+        let version_start = token
+            .find(|c: char| !matches!(c, '<' | '>' | '=' | '^' | '~'))
+            .unwrap_or(token.len());
+        let (token_operator, version) = token.split_at(version_start);
+        operator += token_operator;
+        // The operator is separated from its version, e.g. `>= 1.2.0`
+        if version.is_empty() {
+            continue;
+        }
+        let version = version.trim_start_matches('v');
+        if operator.is_empty() && !matches!(version, "*" | "x" | "X") {
+            operator = String::from("=");
+        }
+        comparators.push(format!("{}{}", operator, version));
+        operator.clear();
+    }
+    comparators.join(", ")
+}
+
+impl Ecosystem for Npm {
+    fn name(&self) -> &'static str {
+        "npm"
+    }
+
+    fn requirements(&self) -> Vec<Requirement> {
+        vec![Requirement {
+            tool: "npm",
+            args: &["--version"],
+            min_version: Some((5, 0, 0)),
+            reason: String::from("to install and package npm projects"),
+        }]
+    }
+
+    fn manifest(&self, folder: &str) -> Result<Manifest> {
+        let package_json_path = PathBuf::from(folder).join("package.json");
+        let package_json = fs::read_to_string(package_json_path.clone())
+            .with_context(|| format!("reading {}", package_json_path.to_string_lossy()))?;
+        let package: Package = serde_json::from_str(&package_json)
+            .with_context(|| format!("Trying to parse {}", package_json_path.to_string_lossy()))?;
+        let dependencies = [
+            (DependencyKind::Normal, &package.dependencies),
+            (DependencyKind::Dev, &package.dev_dependencies),
+            (DependencyKind::Peer, &package.peer_dependencies),
+            (DependencyKind::Optional, &package.optional_dependencies),
+        ]
+        .iter()
+        .flat_map(|(kind, dependencies)| {
+            dependencies
+                .iter()
+                .map(move |(name, requirement)| Dependency {
+                    name: name.clone(),
+                    requirement: requirement.clone(),
+                    kind: *kind,
+                })
+        })
+        .collect();
+        Ok(Manifest {
+            name: package.name,
+            version: package.version,
+            dependencies,
+        })
+    }
+
+    fn patch(&self, folder: &str, artifacts: &[(String, String)]) -> Result<()> {
+        if artifacts.is_empty() {
+            return Ok(());
+        }
+        edit_package_json(folder, |package| {
+            for (dependency_name, package_path) in artifacts {
+                let relative_package_path = relative_path(package_path, folder)?;
+                let tarball = Value::from(relative_package_path.to_string_lossy());
+                install_from(package, dependency_name, tarball).with_context(|| {
+                    format!("Trying to patch {} for {}", dependency_name, folder)
+                })?;
+            }
+            Ok(())
+        })
+    }
+
+    fn pin_versions(&self, folder: &str, versions: &[(String, String)]) -> Result<()> {
+        // Prereleases are only in the ranges which name them. The dependencies
+        // patched to tarballs are left as is.
+        edit_package_json(folder, |package| {
+            let sections = INSTALLED_SECTIONS.iter().chain(&["peerDependencies"]);
+            for section in sections {
+                if let Some(Value::Object(dependencies)) = package.get_mut(*section) {
+                    for (name, version) in versions {
+                        match dependencies.get_mut(name) {
+                            Some(range) if range.as_str().is_some_and(is_range) => {
+                                *range = Value::from(version.as_str())
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn link_registry(
+        &self,
+        folder: &str,
+        versions: &[(String, String)],
+        registry: &str,
+    ) -> Result<()> {
+        edit_package_json(folder, |package| {
+            for (dependency_name, version) in versions {
+                install_from(package, dependency_name, Value::from(version.as_str()))
+                    .with_context(|| {
+                        format!("Trying to patch {} for {}", dependency_name, folder)
+                    })?;
+            }
+            Ok(())
+        })?;
+        self.pin_versions(folder, versions)?;
+        // Point npm to the registry for this project only
+        let npmrc_path = PathBuf::from(folder).join(".npmrc");
+        let npmrc = fs::read_to_string(&npmrc_path).unwrap_or_default();
+        let mut lines = npmrc
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("registry="))
+            .map(String::from)
+            .collect::<Vec<String>>();
+        lines.push(format!("registry={}", registry));
+        fs::write(&npmrc_path, lines.join("\n") + "\n")
+            .with_context(|| format!("Trying to write {}", npmrc_path.to_string_lossy()))?;
+        Ok(())
+    }
+
+    fn stamp_version(&self, folder: &str, stamp: &str) -> Result<String> {
+        let mut stamped = String::new();
+        edit_package_json(folder, |package| {
+            let version = package
+                .get("version")
+                .and_then(|version| version.as_str())
+                .with_context(|| format!("no version in {}/package.json", folder))?;
+            stamped = prerelease(version, stamp);
+            package.insert(String::from("version"), Value::from(stamped.as_str()));
+            Ok(())
+        })?;
+        Ok(stamped)
+    }
+
+    fn satisfies(&self, version: &str, requirement: &str) -> Option<bool> {
+        let requirement = requirement.trim();
+        if requirement.is_empty() || requirement == "latest" {
+            return Some(true);
+        }
+        // file:, git+https:, npm: aliases, github shorthands and tarball paths
+        if requirement.contains(':') || requirement.contains('/') {
+            return None;
+        }
+        let version = semver::Version::parse(version).ok()?;
+        let mut satisfied = false;
+        for range in requirement.split("||") {
+            let range = semver::VersionReq::parse(&comparators(range.trim())).ok()?;
+            satisfied |= range.matches(&version);
+        }
+        Some(satisfied)
+    }
+
+    fn update_lockfile(&self, folder: &str, artifacts: &[(String, String)]) -> Result<bool> {
+        let folder_path = Path::new(folder);
+        let siblings = artifacts
+            .iter()
+            .map(|(name, tarball)| sibling(folder, name, tarball))
+            .collect::<Result<Vec<Sibling>>>()?;
+        let package = read_json(&folder_path.join("package.json"))?;
+        if folder_path.join(PACKAGE_LOCK).exists() {
+            let mut lock = Value::Object(read_json(&folder_path.join(PACKAGE_LOCK))?);
+            lockfile::update_package_lock(&mut lock, &package, &siblings)
+                .with_context(|| format!("Trying to update {}/{}", folder, PACKAGE_LOCK))?;
+            write_json(
+                &folder_path.join(PACKAGE_LOCK),
+                lock.as_object().unwrap().clone(),
+            )?;
+        } else if folder_path.join(PNPM_LOCK).exists() {
+            let lock = fs::read_to_string(folder_path.join(PNPM_LOCK))?;
+            let lock = lockfile::update_pnpm_lock(&lock, &package, &siblings)
+                .with_context(|| format!("Trying to update {}/{}", folder, PNPM_LOCK))?;
+            fs::write(folder_path.join(PNPM_LOCK), lock)?;
+        } else if folder_path.join(YARN_LOCK).exists() {
+            let lock = fs::read_to_string(folder_path.join(YARN_LOCK))?;
+            let lock = lockfile::update_yarn_lock(&lock, &siblings)
+                .with_context(|| format!("Trying to update {}/{}", folder, YARN_LOCK))?;
+            fs::write(folder_path.join(YARN_LOCK), lock)?;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn lockfile_requirements(&self, folder: &str) -> Vec<Requirement> {
+        match locked_install(Path::new(folder)) {
+            Some((tool @ ("pnpm" | "yarn"), _)) => vec![Requirement {
+                tool,
+                args: &["--version"],
+                min_version: None,
+                reason: format!("by {} to install from its lockfile", folder),
+            }],
+            _ => vec![],
+        }
+    }
+
+    fn install(&self, folder: &str, locked: bool, timeout: Option<Duration>) -> Result<()> {
+        if locked {
+            let (tool, args) = locked_install(Path::new(folder))
+                .with_context(|| format!("No lockfile to install from in {}", folder))?;
+            say!("Installing locked dependencies for {}", folder);
+            command::run(Command::new(tool).current_dir(folder).args(args), timeout)
+                .with_context(|| format!("Trying to install locked dependencies in {}", folder))?;
+            return Ok(());
+        }
+        // Clean up the folder
+        std::fs::remove_file(String::from(
+            PathBuf::from(folder)
+                .join("package-lock.json")
+                .to_string_lossy(),
+        ))
+        .ok();
+        let node_module_path =
+            String::from(PathBuf::from(folder).join("node_modules").to_string_lossy());
+        std::fs::create_dir(&node_module_path).unwrap_or(());
+        std::fs::remove_dir_all(&node_module_path)?;
+        say!("Installing dependencies for {}", folder);
+        command::run(
+            Command::new("npm").current_dir(folder).arg("install"),
+            timeout,
+        )
+        .with_context(|| format!("Trying to install dependencies in {}", folder))?;
+        Ok(())
+    }
+
+    fn dependency_folders(&self) -> &'static [&'static str] {
+        &["node_modules"]
+    }
+
+    fn package(&self, folder: &str, timeout: Option<Duration>) -> Result<String> {
+        say!("Packaging {}", folder);
+        let package_output =
+            command::run(Command::new("npm").current_dir(folder).arg("pack"), timeout)
+                .with_context(|| format!("Trying to pack {}", folder))?;
+        let package_file = package_output
+            .stdout
+            .split('\n')
+            .rfind(|s| !s.is_empty())
+            .with_context(|| format!("npm pack did not report any package file for {}", folder))?;
+        say!("{} generated", package_file);
+        Ok(String::from(
+            PathBuf::from(folder).join(package_file).to_string_lossy(),
+        ))
+    }
+
+    fn publish(
+        &self,
+        folder: &str,
+        artifact: &str,
+        registry: &str,
+        tag: &str,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        say!("Publishing {} to {}", artifact, registry);
+        command::run(
+            Command::new("npm")
+                .current_dir(folder)
+                .arg("publish")
+                .arg(artifact)
+                .args(["--registry", registry, "--tag", tag]),
+            timeout,
+        )
+        .with_context(|| format!("Trying to publish {} to {}", artifact, registry))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let folder = tempfile::tempdir().unwrap();
+        fs::write(
+            folder.path().join("package.json"),
+            r#"{
+              "name": "@x/c",
+              "version": "3.0.0",
+              "dependencies": { "@x/a": "^1.0.0" },
+              "devDependencies": { "@x/b": "^2.0.0" },
+              "peerDependencies": { "@x/d": "^4.0.0" },
+              "optionalDependencies": { "@x/e": "^5.0.0" }
+            }"#,
+        )
+        .unwrap();
+        let mut manifest = Npm.manifest(folder.path().to_str().unwrap()).unwrap();
+        manifest.dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(manifest.name, "@x/c");
+        assert_eq!(manifest.version.as_deref(), Some("3.0.0"));
+        assert_eq!(
+            manifest.dependencies,
+            vec![
+                Dependency {
+                    name: String::from("@x/a"),
+                    requirement: String::from("^1.0.0"),
+                    kind: DependencyKind::Normal,
+                },
+                Dependency {
+                    name: String::from("@x/b"),
+                    requirement: String::from("^2.0.0"),
+                    kind: DependencyKind::Dev,
+                },
+                Dependency {
+                    name: String::from("@x/d"),
+                    requirement: String::from("^4.0.0"),
+                    kind: DependencyKind::Peer,
+                },
+                Dependency {
+                    name: String::from("@x/e"),
+                    requirement: String::from("^5.0.0"),
+                    kind: DependencyKind::Optional,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_satisfies() {
+        assert_eq!(Npm.satisfies("2.1.0", "^2.0.0"), Some(true));
+        assert_eq!(Npm.satisfies("3.0.0", "^2.0.0"), Some(false));
+        assert_eq!(Npm.satisfies("1.2.5", "~1.2.0"), Some(true));
+        assert_eq!(Npm.satisfies("1.2.3", "1.2.3"), Some(true));
+        assert_eq!(Npm.satisfies("1.2.4", "1.2.3"), Some(false));
+        assert_eq!(Npm.satisfies("1.9.0", "1.x"), Some(true));
+        assert_eq!(Npm.satisfies("1.9.0", ">= 1.2.0 <2"), Some(true));
+        assert_eq!(Npm.satisfies("2.0.0", ">=1.2.0 <2"), Some(false));
+        assert_eq!(Npm.satisfies("2.0.0", "1.0.0 - 2.0.0"), Some(true));
+        assert_eq!(Npm.satisfies("3.1.0", "^1.0.0 || ^3.0.0"), Some(true));
+        assert_eq!(Npm.satisfies("3.1.0", "*"), Some(true));
+        assert_eq!(Npm.satisfies("3.1.0", "file:../b"), None);
+        assert_eq!(Npm.satisfies("3.1.0", "../b/x-b-3.1.0.tgz"), None);
+        assert_eq!(Npm.satisfies("3.1.0", "next"), None);
+    }
+
+    #[test]
+    fn test_patch() {
+        let root = tempfile::tempdir().unwrap();
+        let folder = root.path().join("c");
+        fs::create_dir(&folder).unwrap();
+        fs::write(
+            folder.join("package.json"),
+            r#"{
+              "name": "@x/c",
+              "version": "0.0.0",
+              "scripts": { "build": "tsc" },
+              "dependencies": { "@x/a": "^1.0.0", "lodash": "^4.0.0" },
+              "peerDependencies": { "@x/a": "^1.0.0", "@x/d": "^4.0.0" },
+              "optionalDependencies": { "@x/e": "^5.0.0" }
+            }"#,
+        )
+        .unwrap();
+        let tarball = |name: &str| {
+            root.path()
+                .join(name)
+                .join(format!("x-{}.tgz", name))
+                .to_string_lossy()
+                .to_string()
+        };
+        Npm.patch(
+            folder.to_str().unwrap(),
+            &[
+                (String::from("@x/a"), tarball("a")),
+                (String::from("@x/d"), tarball("d")),
+                (String::from("@x/e"), tarball("e")),
+            ],
+        )
+        .unwrap();
+        let package_json = fs::read_to_string(folder.join("package.json")).unwrap();
+        let package: Value = serde_json::from_str(&package_json).unwrap();
+        assert_eq!(package["scripts"]["build"], "tsc");
+        assert_eq!(package["dependencies"]["@x/a"], "../a/x-a.tgz");
+        assert_eq!(package["dependencies"]["lodash"], "^4.0.0");
+        assert_eq!(package["optionalDependencies"]["@x/e"], "../e/x-e.tgz");
+        // Peer ranges are left as is, the tarball of a peer-only dependency is
+        // installed as a dev dependency
+        assert_eq!(package["peerDependencies"]["@x/a"], "^1.0.0");
+        assert_eq!(package["peerDependencies"]["@x/d"], "^4.0.0");
+        assert_eq!(package["devDependencies"]["@x/d"], "../d/x-d.tgz");
+        assert!(package
+            .get("devDependencies")
+            .unwrap()
+            .get("@x/a")
+            .is_none());
+        // The order of the fields is kept
+        assert!(package_json.find("\"scripts\"") < package_json.find("\"dependencies\""));
+
+        let folder = folder.to_str().unwrap();
+        Npm.pin_versions(
+            folder,
+            &[(String::from("@x/d"), String::from("4.1.0-integ.7.abc1234"))],
+        )
+        .unwrap();
+        assert_eq!(
+            Npm.stamp_version(folder, "integ.7.def5678").unwrap(),
+            "0.0.0-integ.7.def5678"
+        );
+        assert_eq!(
+            Npm.stamp_version(folder, "integ.8.def5678").unwrap(),
+            "0.0.0-integ.8.def5678"
+        );
+        let package = Npm.manifest(folder).unwrap();
+        assert_eq!(package.version.as_deref(), Some("0.0.0-integ.8.def5678"));
+        assert!(package.dependencies.contains(&Dependency {
+            name: String::from("@x/d"),
+            requirement: String::from("4.1.0-integ.7.abc1234"),
+            kind: DependencyKind::Peer,
+        }));
+
+        // Through a registry, the exact versions are installed instead
+        fs::write(
+            Path::new(folder).join(".npmrc"),
+            "registry=https://registry.npmjs.org/\nfund=false\n",
+        )
+        .unwrap();
+        Npm.link_registry(
+            folder,
+            &[
+                (String::from("@x/a"), String::from("1.0.0-integ.8.abc")),
+                (String::from("@x/d"), String::from("4.1.0-integ.8.abc")),
+            ],
+            "http://localhost:4873/",
+        )
+        .unwrap();
+        let package_json = fs::read_to_string(Path::new(folder).join("package.json")).unwrap();
+        let package: Value = serde_json::from_str(&package_json).unwrap();
+        assert_eq!(package["dependencies"]["@x/a"], "1.0.0-integ.8.abc");
+        assert_eq!(package["peerDependencies"]["@x/a"], "1.0.0-integ.8.abc");
+        assert_eq!(package["devDependencies"]["@x/d"], "4.1.0-integ.8.abc");
+        assert_eq!(
+            fs::read_to_string(Path::new(folder).join(".npmrc")).unwrap(),
+            "fund=false\nregistry=http://localhost:4873/\n"
+        );
+    }
+
+    #[test]
+    fn test_lockfile_requirements() {
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().to_string_lossy().to_string();
+        assert!(Npm.lockfile_requirements(&path).is_empty());
+        fs::write(folder.path().join(YARN_LOCK), "").unwrap();
+        let requirements = Npm.lockfile_requirements(&path);
+        assert_eq!(requirements.len(), 1);
+        assert_eq!(requirements[0].tool, "yarn");
+        assert_eq!(
+            requirements[0].reason,
+            format!("by {} to install from its lockfile", path)
+        );
+        // npm itself is already required
+        fs::write(folder.path().join(PACKAGE_LOCK), "{}").unwrap();
+        assert!(Npm.lockfile_requirements(&path).is_empty());
+    }
+
+    #[test]
+    fn test_pin_versions() {
+        let folder = tempfile::tempdir().unwrap();
+        fs::write(
+            folder.path().join("package.json"),
+            r#"{
+  "name": "@x/b",
+  "version": "1.0.0",
+  "dependencies": { "@x/a": "^2.3.0", "lodash": "^4.0.0" },
+  "devDependencies": { "@x/a": "~2.3.0" },
+  "optionalDependencies": { "@x/a": "../a/x-a-2.3.0.tgz" },
+  "peerDependencies": { "@x/a": "2.x" }
+}"#,
+        )
+        .unwrap();
+        let folder = folder.path().to_str().unwrap();
+        let stamped = String::from("2.3.0-integ.7.abc1234");
+        Npm.pin_versions(folder, &[(String::from("@x/a"), stamped.clone())])
+            .unwrap();
+        let package_json = fs::read_to_string(PathBuf::from(folder).join("package.json")).unwrap();
+        let package: Value = serde_json::from_str(&package_json).unwrap();
+        assert_eq!(package["dependencies"]["@x/a"], stamped.as_str());
+        assert_eq!(package["dependencies"]["lodash"], "^4.0.0");
+        assert_eq!(package["devDependencies"]["@x/a"], stamped.as_str());
+        assert_eq!(
+            package["optionalDependencies"]["@x/a"],
+            "../a/x-a-2.3.0.tgz"
+        );
+        assert_eq!(package["peerDependencies"]["@x/a"], stamped.as_str());
+    }
+}
@@ -0,0 +1,703 @@
+//! Python projects described by the `[project]` table of a `pyproject.toml`.
+//!
+//! Each project gets its own virtualenv in `.venv`. The wheels built for the
+//! other integrated projects are installed in it, along with the rest of the
+//! dependencies, before the build commands run with the virtualenv activated.
+use super::{Dependency, DependencyKind, Ecosystem, Manifest};
+use crate::command::{self, Requirement};
+use crate::event::say;
+use anyhow::{Context, Result};
+use std::cmp::Ordering;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use toml_edit::DocumentMut;
+
+const VENV: &str = ".venv";
+// The dependencies to install in the virtualenv, written by `patch`
+const REQUIREMENTS: &str = "integ-requirements.txt";
+const WHEELS: &str = "dist";
+
+pub struct Python;
+
+// Normalize a project name as per PEP 503: `My_Package` and `my-package`
+// refer to the same project
+fn normalize(name: &str) -> String {
+    let mut normalized = String::new();
+    for c in name.trim().chars() {
+        match c {
+            '-' | '_' | '.' if normalized.ends_with('-') => (),
+            '-' | '_' | '.' => normalized.push('-'),
+            c => normalized.push(c.to_ascii_lowercase()),
+        }
+    }
+    normalized
+}
+
+// Split a PEP 508 requirement such as `requests[socks]>=2; python_version>"3"`
+// into its name and what follows the extras
+fn split_requirement(requirement: &str) -> (&str, &str) {
+    let requirement = requirement.trim();
+    let end = requirement
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'))
+        .unwrap_or(requirement.len());
+    let (name, rest) = requirement.split_at(end);
+    let rest = rest.trim_start();
+    let rest = match (rest.strip_prefix('['), rest.find(']')) {
+        (Some(_), Some(extras_end)) => &rest[extras_end + 1..],
+        _ => rest,
+    };
+    (name, rest.trim())
+}
+
+// A segment of the local label of a version, the numbers sorting after the
+// other segments
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum LocalSegment {
+    Text(String),
+    Number(u64),
+}
+
+// A PEP 440 version such as `1!2.0.1rc1.post2.dev3+integ.1`
+#[derive(Debug, Clone, PartialEq)]
+struct Version {
+    epoch: u64,
+    release: Vec<u64>,
+    /// `a`, `b` or `rc` as 0, 1 or 2, with its number
+    pre: Option<(u8, u64)>,
+    post: Option<u64>,
+    dev: Option<u64>,
+    local: Vec<LocalSegment>,
+}
+
+// The labels following the release of a version
+enum Label {
+    Pre(u8),
+    Post,
+    Dev,
+}
+
+// The number following a label such as `rc` in a version, 0 if implicit
+fn label_number(rest: &str) -> Option<(u64, &str)> {
+    let rest = rest.trim_start_matches(['.', '-', '_']);
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let number = if end == 0 {
+        0
+    } else {
+        rest[..end].parse().ok()?
+    };
+    Some((number, &rest[end..]))
+}
+
+impl Version {
+    fn parse(version: &str) -> Option<Version> {
+        let version = version.trim().to_ascii_lowercase();
+        let version = version.strip_prefix('v').unwrap_or(&version);
+        let (public, local) = match version.split_once('+') {
+            Some((public, local)) => (public, Some(local)),
+            None => (version, None),
+        };
+        let (epoch, mut rest) = match public.split_once('!') {
+            Some((epoch, rest)) => (epoch.parse().ok()?, rest),
+            None => (0, public),
+        };
+        let mut release = vec![];
+        loop {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            release.push(rest[..end].parse().ok()?);
+            rest = &rest[end..];
+            match rest.strip_prefix('.') {
+                Some(next) if next.starts_with(|c: char| c.is_ascii_digit()) => rest = next,
+                _ => break,
+            }
+        }
+        let mut parsed = Version {
+            epoch,
+            release,
+            pre: None,
+            post: None,
+            dev: None,
+            local: vec![],
+        };
+        // The longer labels first, as `rc` starts with `r`
+        let labels = [
+            ("alpha", Label::Pre(0)),
+            ("a", Label::Pre(0)),
+            ("beta", Label::Pre(1)),
+            ("b", Label::Pre(1)),
+            ("preview", Label::Pre(2)),
+            ("pre", Label::Pre(2)),
+            ("rc", Label::Pre(2)),
+            ("c", Label::Pre(2)),
+            ("post", Label::Post),
+            ("rev", Label::Post),
+            ("r", Label::Post),
+            ("dev", Label::Dev),
+        ];
+        while !rest.is_empty() {
+            // `1.0-1` is the implicit post-release `1.0.post1`
+            if let Some(number) = rest.strip_prefix('-') {
+                if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) {
+                    parsed.post = Some(number.parse().ok()?);
+                    break;
+                }
+            }
+            let label = rest.trim_start_matches(['.', '-', '_']);
+            let (name, kind) = labels.iter().find(|(name, _)| label.starts_with(name))?;
+            let (number, next) = label_number(&label[name.len()..])?;
+            match kind {
+                Label::Pre(kind) => parsed.pre = Some((*kind, number)),
+                Label::Post => parsed.post = Some(number),
+                Label::Dev => parsed.dev = Some(number),
+            }
+            rest = next;
+        }
+        for segment in local
+            .into_iter()
+            .flat_map(|local| local.split(['.', '-', '_']))
+        {
+            parsed.local.push(match segment.parse() {
+                Ok(number) => LocalSegment::Number(number),
+                Err(_) if !segment.is_empty() => LocalSegment::Text(segment.to_string()),
+                Err(_) => return None,
+            });
+        }
+        Some(parsed)
+    }
+
+    fn is_prerelease(&self) -> bool {
+        self.pre.is_some() || self.dev.is_some()
+    }
+
+    // The version without its local label
+    fn public(&self) -> Version {
+        Version {
+            local: vec![],
+            ..self.clone()
+        }
+    }
+
+    // The epoch and the release, whose trailing zeros do not count
+    fn release_key(&self) -> (u64, &[u64]) {
+        let mut release = &self.release[..];
+        while let [rest @ .., 0] = release {
+            release = rest;
+        }
+        (self.epoch, release)
+    }
+
+    // The key versions are ordered by, a development release coming before
+    // the prereleases
+    #[allow(clippy::type_complexity)]
+    fn key(
+        &self,
+    ) -> (
+        (u64, &[u64]),
+        (i8, u64),
+        Option<u64>,
+        (bool, u64),
+        &[LocalSegment],
+    ) {
+        let pre = match (self.pre, self.post, self.dev) {
+            (Some((kind, number)), _, _) => (kind as i8, number),
+            (None, None, Some(_)) => (-1, 0),
+            (None, _, _) => (3, 0),
+        };
+        let dev = (self.dev.is_none(), self.dev.unwrap_or(0));
+        (self.release_key(), pre, self.post, dev, &self.local)
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.key().cmp(&other.key()))
+    }
+}
+
+// Whether `version` matches the prefix `prefix` of a `==X.*` specifier
+fn matches_prefix(version: &Version, prefix: &Version) -> bool {
+    let mut release = version.release.clone();
+    release.resize(release.len().max(prefix.release.len()), 0);
+    version.epoch == prefix.epoch && release.starts_with(&prefix.release)
+}
+
+// Whether `version` matches a single PEP 440 specifier such as `~=1.2`
+fn matches(version: &Version, specifier: &str) -> Option<bool> {
+    let specifier = specifier.trim();
+    let operator_end = specifier
+        .find(|c: char| !"=!<>~".contains(c))
+        .unwrap_or(specifier.len());
+    let (operator, spec) = specifier.split_at(operator_end);
+    let spec = spec.trim();
+    if let Some(prefix) = spec.strip_suffix(".*") {
+        let prefix = Version::parse(prefix)?;
+        return match operator {
+            "==" => Some(matches_prefix(version, &prefix)),
+            "!=" => Some(!matches_prefix(version, &prefix)),
+            _ => None,
+        };
+    }
+    let spec = Version::parse(spec)?;
+    // The local label of the candidate only counts if the specifier has one
+    let candidate = if spec.local.is_empty() {
+        version.public()
+    } else {
+        version.clone()
+    };
+    let same_release = candidate.release_key() == spec.release_key();
+    Some(match operator {
+        "==" => candidate.partial_cmp(&spec) == Some(Ordering::Equal),
+        "!=" => candidate.partial_cmp(&spec) != Some(Ordering::Equal),
+        "<=" => candidate <= spec,
+        ">=" => candidate >= spec,
+        // `<1.2` excludes the prereleases of 1.2, `>1.2` its post-releases
+        "<" => {
+            candidate < spec
+                && (spec.is_prerelease() || !candidate.is_prerelease() || !same_release)
+        }
+        ">" => {
+            candidate > spec && (spec.post.is_some() || candidate.post.is_none() || !same_release)
+        }
+        "~=" if spec.release.len() >= 2 => {
+            let prefix = Version {
+                release: spec.release[..spec.release.len() - 1].to_vec(),
+                ..spec.public()
+            };
+            candidate >= spec && matches_prefix(&candidate, &prefix)
+        }
+        _ => return None,
+    })
+}
+
+fn read_pyproject(folder: &str) -> Result<DocumentMut> {
+    let pyproject_path = PathBuf::from(folder).join("pyproject.toml");
+    let pyproject = fs::read_to_string(&pyproject_path)
+        .with_context(|| format!("reading {}", pyproject_path.to_string_lossy()))?;
+    pyproject
+        .parse::<DocumentMut>()
+        .with_context(|| format!("Trying to parse {}", pyproject_path.to_string_lossy()))
+}
+
+// The requirements of `dependencies` and of every group of
+// `optional-dependencies`, which are installed as well
+fn requirements(pyproject: &DocumentMut) -> Vec<(DependencyKind, String)> {
+    let project = match pyproject.get("project") {
+        Some(project) => project,
+        None => return vec![],
+    };
+    let strings = |kind: DependencyKind, array: Option<&toml_edit::Array>| {
+        array
+            .into_iter()
+            .flat_map(|array| array.iter())
+            .filter_map(|requirement| requirement.as_str().map(|r| (kind, String::from(r))))
+            .collect::<Vec<_>>()
+    };
+    let mut requirements = strings(
+        DependencyKind::Normal,
+        project.get("dependencies").and_then(|d| d.as_array()),
+    );
+    if let Some(groups) = project
+        .get("optional-dependencies")
+        .and_then(|groups| groups.as_table_like())
+    {
+        for (_, group) in groups.iter() {
+            requirements.extend(strings(DependencyKind::Optional, group.as_array()));
+        }
+    }
+    requirements
+}
+
+fn venv_python(folder: &str) -> PathBuf {
+    PathBuf::from(folder).join(VENV).join("bin").join("python")
+}
+
+impl Ecosystem for Python {
+    fn name(&self) -> &'static str {
+        "python"
+    }
+
+    fn requirements(&self) -> Vec<Requirement> {
+        vec![Requirement {
+            tool: "python3",
+            args: &["--version"],
+            min_version: Some((3, 7, 0)),
+            reason: String::from("to create virtualenvs and build wheels"),
+        }]
+    }
+
+    fn manifest(&self, folder: &str) -> Result<Manifest> {
+        let pyproject = read_pyproject(folder)?;
+        let project = pyproject
+            .get("project")
+            .with_context(|| format!("no [project] in {}/pyproject.toml", folder))?;
+        let name = project
+            .get("name")
+            .and_then(|name| name.as_str())
+            .with_context(|| format!("no project name in {}/pyproject.toml", folder))?;
+        // Not set when declared as `dynamic`
+        let version = project
+            .get("version")
+            .and_then(|version| version.as_str())
+            .map(String::from);
+        let dependencies = requirements(&pyproject)
+            .iter()
+            .map(|(kind, requirement)| {
+                let (name, specifier) = split_requirement(requirement);
+                Dependency {
+                    name: normalize(name),
+                    requirement: if specifier.is_empty() {
+                        String::from("*")
+                    } else {
+                        specifier.to_string()
+                    },
+                    kind: *kind,
+                }
+            })
+            .collect();
+        Ok(Manifest {
+            name: normalize(name),
+            version,
+            dependencies,
+        })
+    }
+
+    fn patch(&self, folder: &str, artifacts: &[(String, String)]) -> Result<()> {
+        // (normalized name, requirement on the wheel) of the integrated projects
+        let mut wheels: Vec<(String, String)> = vec![];
+        for (name, wheel) in artifacts {
+            let wheel = fs::canonicalize(wheel)
+                .with_context(|| format!("Trying to find the wheel {}", wheel))?;
+            // The wheels of the dependency's own dependencies are needed as
+            // well, they are in the requirements it was installed with
+            let dependency_folder = wheel.parent().and_then(Path::parent).unwrap();
+            let inherited = fs::read_to_string(dependency_folder.join(REQUIREMENTS))
+                .unwrap_or_default()
+                .lines()
+                .filter_map(|line| line.split_once(" @ file://"))
+                .map(|(name, path)| (normalize(name), format!("{} @ file://{}", name, path)))
+                .collect::<Vec<_>>();
+            for (name, requirement) in inherited.into_iter().chain(std::iter::once((
+                normalize(name),
+                format!("{} @ file://{}", name, wheel.to_string_lossy()),
+            ))) {
+                if !wheels.iter().any(|(known, _)| *known == name) {
+                    wheels.push((name, requirement));
+                }
+            }
+        }
+        let others = requirements(&read_pyproject(folder)?)
+            .into_iter()
+            .map(|(_, requirement)| requirement)
+            .filter(|requirement| {
+                let name = normalize(split_requirement(requirement).0);
+                !wheels.iter().any(|(known, _)| *known == name)
+            })
+            .collect::<Vec<String>>();
+        let mut content = String::from("# Generated by integ\n");
+        for requirement in wheels
+            .into_iter()
+            .map(|(_, requirement)| requirement)
+            .chain(others)
+        {
+            content += &requirement;
+            content += "\n";
+        }
+        let requirements_path = PathBuf::from(folder).join(REQUIREMENTS);
+        fs::write(&requirements_path, content)
+            .with_context(|| format!("Trying to write {}", requirements_path.to_string_lossy()))?;
+        Ok(())
+    }
+
+    fn stamp_version(&self, folder: &str, stamp: &str) -> Result<String> {
+        let mut pyproject = read_pyproject(folder)?;
+        let project = pyproject
+            .get_mut("project")
+            .and_then(|project| project.as_table_like_mut())
+            .with_context(|| format!("no [project] in {}/pyproject.toml", folder))?;
+        let version = project
+            .get("version")
+            .and_then(|version| version.as_str())
+            .with_context(|| format!("no static version in {}/pyproject.toml", folder))?;
+        // PEP 440 has no prerelease labels, but local versions match the same
+        // specifiers as the version they are based on
+        let release = version
+            .split_once("+integ.")
+            .map_or(version, |(release, _)| release);
+        let stamped = format!("{}+{}", release, stamp);
+        project.insert("version", toml_edit::value(stamped.as_str()));
+        let pyproject_path = PathBuf::from(folder).join("pyproject.toml");
+        fs::write(&pyproject_path, pyproject.to_string())
+            .with_context(|| format!("Trying to patch {}", pyproject_path.to_string_lossy()))?;
+        Ok(stamped)
+    }
+
+    fn satisfies(&self, version: &str, requirement: &str) -> Option<bool> {
+        // The environment markers are not evaluated
+        let requirement = requirement.split(';').next().unwrap_or("").trim();
+        if requirement.is_empty() || requirement == "*" {
+            return Some(true);
+        }
+        // A direct reference such as `@ file:///wheels/a.whl`
+        if requirement.starts_with('@') {
+            return None;
+        }
+        let raw_version = version;
+        let version = Version::parse(version)?;
+        let mut satisfied = true;
+        for specifier in requirement.split(',') {
+            // Arbitrary equality compares the strings
+            satisfied &= match specifier.trim().strip_prefix("===") {
+                Some(expected) => expected.trim().eq_ignore_ascii_case(raw_version.trim()),
+                None => matches(&version, specifier)?,
+            };
+        }
+        Some(satisfied)
+    }
+
+    fn install(&self, folder: &str, _locked: bool, timeout: Option<Duration>) -> Result<()> {
+        say!("Installing dependencies for {}", folder);
+        command::run(
+            Command::new("python3")
+                .current_dir(folder)
+                .args(["-m", "venv", VENV]),
+            timeout,
+        )
+        .with_context(|| format!("Trying to create a virtualenv in {}", folder))?;
+        command::run(
+            Command::new(venv_python(folder))
+                .current_dir(folder)
+                .args(["-m", "pip", "install", "--disable-pip-version-check"])
+                .args(["-r", REQUIREMENTS]),
+            timeout,
+        )
+        .with_context(|| format!("Trying to install dependencies in {}", folder))?;
+        Ok(())
+    }
+
+    fn dependency_folders(&self) -> &'static [&'static str] {
+        &[VENV]
+    }
+
+    fn environment(&self, folder: &str) -> Vec<(String, String)> {
+        let venv = fs::canonicalize(folder)
+            .unwrap_or_else(|_| PathBuf::from(folder))
+            .join(VENV);
+        let path = match env::var_os("PATH") {
+            Some(path) => format!(
+                "{}:{}",
+                venv.join("bin").to_string_lossy(),
+                path.to_string_lossy()
+            ),
+            None => venv.join("bin").to_string_lossy().to_string(),
+        };
+        vec![
+            (
+                String::from("VIRTUAL_ENV"),
+                venv.to_string_lossy().to_string(),
+            ),
+            (String::from("PATH"), path),
+        ]
+    }
+
+    fn package(&self, folder: &str, timeout: Option<Duration>) -> Result<String> {
+        say!("Building the wheel of {}", folder);
+        let wheels = PathBuf::from(folder).join(WHEELS);
+        // Only keep the wheel built now
+        if wheels.exists() {
+            fs::remove_dir_all(&wheels)
+                .with_context(|| format!("Trying to clean {}", wheels.to_string_lossy()))?;
+        }
+        command::run(
+            Command::new(venv_python(folder))
+                .current_dir(folder)
+                .args(["-m", "pip", "wheel", "--disable-pip-version-check"])
+                .args(["--no-deps", "--wheel-dir", WHEELS, "."]),
+            timeout,
+        )
+        .with_context(|| format!("Trying to build the wheel of {}", folder))?;
+        let wheel = fs::read_dir(&wheels)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "whl"))
+            .with_context(|| format!("no wheel built in {}", wheels.to_string_lossy()))?;
+        Ok(wheel.to_string_lossy().to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A PEP 517 backend building the wheel of `a` without any build dependency,
+    // so that the test does not need the network
+    const BACKEND: &str = r#"
+import base64, hashlib, os, zipfile
+
+def build_wheel(wheel_directory, config_settings=None, metadata_directory=None):
+    name = "a-0.1.0-py3-none-any.whl"
+    files = {
+        "a/__init__.py": "VALUE = 42\n",
+        "a-0.1.0.dist-info/METADATA": "Metadata-Version: 2.1\nName: a\nVersion: 0.1.0\n",
+        "a-0.1.0.dist-info/WHEEL": "Wheel-Version: 1.0\nGenerator: integ\nRoot-Is-Purelib: true\nTag: py3-none-any\n",
+    }
+    record = []
+    with zipfile.ZipFile(os.path.join(wheel_directory, name), "w") as wheel:
+        for path, content in files.items():
+            wheel.writestr(path, content)
+            digest = hashlib.sha256(content.encode()).digest()
+            digest = base64.urlsafe_b64encode(digest).rstrip(b"=").decode()
+            record.append("%s,sha256=%s,%d" % (path, digest, len(content)))
+        record.append("a-0.1.0.dist-info/RECORD,,")
+        wheel.writestr("a-0.1.0.dist-info/RECORD", "\n".join(record) + "\n")
+    return name
+"#;
+
+    #[test]
+    fn test_split_requirement() {
+        assert_eq!(split_requirement("a"), ("a", ""));
+        assert_eq!(
+            split_requirement("My_Package >= 1.0"),
+            ("My_Package", ">= 1.0")
+        );
+        assert_eq!(
+            split_requirement(r#"requests[socks]>=2; python_version > "3""#),
+            ("requests", r#">=2; python_version > "3""#)
+        );
+        assert_eq!(normalize("My_Package"), "my-package");
+        assert_eq!(normalize("zope.interface"), "zope-interface");
+    }
+
+    #[test]
+    fn test_satisfies() {
+        assert_eq!(Python.satisfies("1.4.2", ">=1.2,<2"), Some(true));
+        assert_eq!(Python.satisfies("2.0", ">=1.2, <2"), Some(false));
+        assert_eq!(Python.satisfies("1.4.2", "~=1.4"), Some(true));
+        assert_eq!(Python.satisfies("2.0", "~=1.4"), Some(false));
+        assert_eq!(Python.satisfies("1.4.2", "~=1.4.0"), Some(true));
+        assert_eq!(Python.satisfies("1.5.0", "~=1.4.0"), Some(false));
+        assert_eq!(Python.satisfies("1.4", "==1.4.0"), Some(true));
+        assert_eq!(Python.satisfies("1.4.1", "==1.4.*"), Some(true));
+        assert_eq!(Python.satisfies("1.5", "!=1.4.*"), Some(true));
+        assert_eq!(Python.satisfies("1.4", "!=1.4"), Some(false));
+        assert_eq!(Python.satisfies("1.4", "===1.4"), Some(true));
+        assert_eq!(Python.satisfies("1.4.0", "===1.4"), Some(false));
+        // Prereleases and post-releases
+        assert_eq!(Python.satisfies("2.0rc1", "<2"), Some(false));
+        assert_eq!(Python.satisfies("2.0.dev1", "<2.0a1"), Some(true));
+        assert_eq!(Python.satisfies("2.0.post1", ">2"), Some(false));
+        assert_eq!(Python.satisfies("2.0.post1", ">=2"), Some(true));
+        assert_eq!(Python.satisfies("1!1.0", ">=2"), Some(true));
+        // The stamped versions match like the version they are based on
+        assert_eq!(Python.satisfies("1.2+integ.1.abc1234", "==1.2"), Some(true));
+        assert_eq!(Python.satisfies("1.2+integ.1.abc1234", ">1.2"), Some(false));
+        assert_eq!(Python.satisfies("1.2+integ.1.abc1234", ">=1.2"), Some(true));
+        assert_eq!(
+            Python.satisfies("1.2", r#">=1.0; python_version > "3""#),
+            Some(true)
+        );
+        assert_eq!(Python.satisfies("1.2", "*"), Some(true));
+        assert_eq!(Python.satisfies("1.2", "@ file:///wheels/a.whl"), None);
+        assert_eq!(Python.satisfies("1.2", ">=latest"), None);
+    }
+
+    #[test]
+    fn test_stamp_version() {
+        let folder = tempfile::tempdir().unwrap();
+        fs::write(
+            folder.path().join("pyproject.toml"),
+            "[project]\nname = \"a\"\nversion = \"1.2\"\n",
+        )
+        .unwrap();
+        let folder = folder.path().to_str().unwrap();
+        assert_eq!(
+            Python.stamp_version(folder, "integ.1.abc1234").unwrap(),
+            "1.2+integ.1.abc1234"
+        );
+        assert_eq!(
+            Python.stamp_version(folder, "integ.2.abc1234").unwrap(),
+            "1.2+integ.2.abc1234"
+        );
+        assert_eq!(
+            Python.manifest(folder).unwrap().version.as_deref(),
+            Some("1.2+integ.2.abc1234")
+        );
+    }
+
+    #[test]
+    fn test_wheels() {
+        let root = tempfile::tempdir().unwrap();
+        let a = root.path().join("a");
+        let b = root.path().join("b");
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        fs::write(a.join("backend.py"), BACKEND).unwrap();
+        fs::write(
+            a.join("pyproject.toml"),
+            r#"[build-system]
+requires = []
+build-backend = "backend"
+backend-path = ["."]
+
+[project]
+name = "A"
+version = "0.1.0"
+"#,
+        )
+        .unwrap();
+        fs::write(
+            b.join("pyproject.toml"),
+            r#"[project]
+name = "b"
+dynamic = ["version"]
+dependencies = ["a>=0.1"]
+
+[project.optional-dependencies]
+test = []
+"#,
+        )
+        .unwrap();
+        let a = a.to_string_lossy().to_string();
+        let b = b.to_string_lossy().to_string();
+
+        let manifest = Python.manifest(&b).unwrap();
+        assert_eq!(manifest.name, "b");
+        assert_eq!(manifest.version, None);
+        assert_eq!(
+            manifest.dependencies,
+            vec![Dependency {
+                name: String::from("a"),
+                requirement: String::from(">=0.1"),
+                kind: DependencyKind::Normal,
+            }]
+        );
+
+        Python.patch(&a, &[]).unwrap();
+        Python.install(&a, false, None).unwrap();
+        let wheel = Python.package(&a, None).unwrap();
+        assert!(wheel.ends_with("a-0.1.0-py3-none-any.whl"));
+
+        // b gets the wheel of a installed in its virtualenv
+        Python
+            .patch(&b, &[(String::from("A"), wheel.clone())])
+            .unwrap();
+        let requirements = fs::read_to_string(Path::new(&b).join(REQUIREMENTS)).unwrap();
+        assert!(requirements.contains(&format!("A @ file://{}", wheel)));
+        assert!(!requirements.contains("a>=0.1"));
+        Python.install(&b, false, None).unwrap();
+        let output = command::run(
+            Command::new("bash")
+                .current_dir(&b)
+                .envs(Python.environment(&b))
+                .args(["-c", "python -c 'import a; print(a.VALUE)'"]),
+            None,
+        )
+        .unwrap();
+        assert_eq!(output.stdout, "42\n");
+    }
+}
@@ -0,0 +1,273 @@
+//! Progress of a run as events, printed one JSON object per line with
+//! `--output-format json` so that the run can be followed by another program.
+//!
+//! The messages meant for humans are printed with [`say!`] which moves them
+//! to stderr in that mode, leaving stdout to the events.
+use crate::command::CommandError;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// How the progress of a run is printed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+impl OutputFormat {
+    pub const NAMES: [&'static str; 2] = ["human", "json"];
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "human" => Ok(OutputFormat::Human),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!(
+                "unknown output format `{}`, expected one of {}",
+                format,
+                OutputFormat::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Print the events on stdout (and the human messages on stderr) from now
+/// on with [`OutputFormat::Json`].
+pub fn set_output_format(format: OutputFormat) {
+    JSON.store(format == OutputFormat::Json, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Print a message for humans, on stdout unless the events are. Takes the
+/// arguments of `format!`.
+#[macro_export]
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if $crate::event::is_json() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+pub use say;
+
+/// Print a warning or an error for humans, on stderr in both formats.
+pub(crate) fn warn(message: impl fmt::Display) {
+    eprintln!("{}", message);
+}
+
+/// A step of the integration of a project.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    Clone,
+    Copy,
+    Install,
+    Build,
+    Pack,
+    Publish,
+}
+
+/// Something which happened during a run. The projects are named by their
+/// folder until they are resolved, by their package name afterwards.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    RunStarted {
+        /// The folder name of each entry of the configuration
+        projects: Vec<String>,
+    },
+    StageStarted {
+        stage: &'static str,
+    },
+    StageFinished {
+        stage: &'static str,
+        duration_ms: u128,
+        success: bool,
+    },
+    ProjectRetrieved {
+        project: String,
+        folder: String,
+        /// False if the folder already existed and was left untouched
+        fetched: bool,
+    },
+    Resolved {
+        /// The package names in build order
+        order: Vec<String>,
+        /// The folder of each package
+        folders: BTreeMap<String, String>,
+    },
+    StepStarted {
+        project: String,
+        step: Step,
+        command: Option<String>,
+    },
+    StepFinished {
+        project: String,
+        step: Step,
+        command: Option<String>,
+        duration_ms: u128,
+        /// None if the step did not fail on a command
+        exit_code: Option<i32>,
+        success: bool,
+    },
+    ArtifactProduced {
+        project: String,
+        path: String,
+        size: u64,
+    },
+    /// A step failed and is attempted again after `delay_ms`
+    RetryScheduled {
+        step: String,
+        /// The attempt which failed, from 1
+        attempt: u32,
+        attempts: u32,
+        delay_ms: u64,
+        error: String,
+    },
+    ProjectFinished {
+        project: String,
+        /// As labelled in the summary: succeeded, cached, failed or blocked
+        status: &'static str,
+        error: Option<String>,
+    },
+    RunFinished {
+        success: bool,
+        duration_ms: u128,
+        error: Option<String>,
+    },
+}
+
+/// Print the event if the events are printed.
+pub(crate) fn emit(event: Event) {
+    if is_json() {
+        println!("{}", serde_json::to_string(&event).unwrap());
+    }
+}
+
+/// Report that attempt `attempt` out of `attempts` of `step` failed with
+/// `error` and that it is retried after `delay`.
+pub(crate) fn retry(
+    step: &str,
+    attempt: u32,
+    attempts: u32,
+    delay: Duration,
+    error: &anyhow::Error,
+) {
+    warn(format_args!(
+        "{} failed (attempt {}/{}): {:#}",
+        step, attempt, attempts, error
+    ));
+    say!("retrying {} in {}ms", step, delay.as_millis());
+    emit(Event::RetryScheduled {
+        step: step.to_string(),
+        attempt,
+        attempts,
+        delay_ms: delay.as_millis() as u64,
+        error: format!("{:#}", error)
+            .lines()
+            .next()
+            .unwrap_or("")
+            .to_string(),
+    });
+}
+
+// The exit code of the command a step failed on, if it failed on one
+fn exit_code<T>(result: &Result<T>) -> Option<i32> {
+    match result {
+        Ok(_) => Some(0),
+        Err(error) => error
+            .chain()
+            .find_map(|cause| match cause.downcast_ref::<CommandError>() {
+                Some(CommandError::NonZeroExit { code, .. }) => Some(*code),
+                _ => None,
+            }),
+    }
+}
+
+/// Run a stage of the integration between a `stage_started` and a
+/// `stage_finished` event.
+pub(crate) fn stage<T>(stage: &'static str, run: impl FnOnce() -> Result<T>) -> Result<T> {
+    emit(Event::StageStarted { stage });
+    let start = Instant::now();
+    let result = run();
+    emit(Event::StageFinished {
+        stage,
+        duration_ms: start.elapsed().as_millis(),
+        success: result.is_ok(),
+    });
+    result
+}
+
+/// Run a step of a project between a `step_started` and a `step_finished`
+/// event, returning its result and how long it took.
+pub(crate) fn step<T>(
+    project: &str,
+    step: Step,
+    command: Option<&str>,
+    run: impl FnOnce() -> Result<T>,
+) -> (Result<T>, Duration) {
+    emit(Event::StepStarted {
+        project: project.to_string(),
+        step,
+        command: command.map(String::from),
+    });
+    let start = Instant::now();
+    let result = run();
+    let duration = start.elapsed();
+    emit(Event::StepFinished {
+        project: project.to_string(),
+        step,
+        command: command.map(String::from),
+        duration_ms: duration.as_millis(),
+        exit_code: exit_code(&result),
+        success: result.is_ok(),
+    });
+    (result, duration)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_events() {
+        let event = Event::StepFinished {
+            project: String::from("@x/a"),
+            step: Step::Build,
+            command: Some(String::from("npm test")),
+            duration_ms: 1200,
+            exit_code: Some(1),
+            success: false,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"step_finished","project":"@x/a","step":"build","command":"npm test","duration_ms":1200,"exit_code":1,"success":false}"#
+        );
+        let failure: Result<()> = Err(anyhow::Error::new(CommandError::NonZeroExit {
+            code: 2,
+            stdout: String::new(),
+            stderr: String::new(),
+        })
+        .context("Trying to build"));
+        assert_eq!(exit_code(&failure), Some(2));
+        assert_eq!(
+            exit_code(&Err::<(), _>(anyhow::anyhow!("no command"))),
+            None
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! Dependency graph between the integrated projects.
+use crate::ecosystem::{DependencyKind, Manifest};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Project name to the names of the integrated projects it depends on.
+pub type Graph = HashMap<String, Vec<String>>;
+
+/// Link the packages through their dependencies of the given kinds, only
+/// keeping the dependencies on other integrated packages.
+pub fn build_dependency_graph(manifests: &[Manifest], kinds: &[DependencyKind]) -> Result<Graph> {
+    let mut graph = Graph::new();
+    let names: Vec<String> = manifests
+        .iter()
+        .map(|manifest| manifest.name.clone())
+        .collect::<_>();
+    for manifest in manifests {
+        let mut dependency_names = vec![];
+        for dependency in &manifest.dependencies {
+            // A package can be both a peer and a dev dependency
+            if kinds.contains(&dependency.kind)
+                && names.contains(&dependency.name)
+                && !dependency_names.contains(&dependency.name)
+            {
+                dependency_names.push(dependency.name.clone());
+            }
+        }
+        graph.insert(manifest.name.clone(), dependency_names);
+    }
+
+    Ok(graph)
+}
+
+/// Order the projects so that each one comes after its dependencies, failing
+/// on a dependency cycle.
+pub fn topo_sort(graph: &Graph) -> Result<Vec<String>> {
+    // `visiting` is the chain of dependents leading to `package`
+    fn topo_sort_rec(
+        graph: &Graph,
+        package: &str,
+        visiting: &mut Vec<String>,
+        result: &mut Vec<String>,
+    ) -> Result<()> {
+        if let Some(start) = visiting.iter().position(|p| p == package) {
+            let mut cycle = visiting[start..].to_vec();
+            cycle.push(package.to_string());
+            return Err(anyhow::anyhow!(
+                "dependency cycle {}, remove the kind of one of these dependencies from `edges`",
+                cycle.join(" -> ")
+            ));
+        }
+        let dependencies = graph.get(package).unwrap();
+        let unresolved_dependencies = dependencies
+            .iter()
+            .filter(|p| !result.contains(p))
+            .cloned()
+            .collect::<Vec<String>>();
+        visiting.push(package.to_string());
+        for ud in unresolved_dependencies {
+            topo_sort_rec(graph, &ud, visiting, result)?;
+        }
+        visiting.pop();
+        if !result.contains(&package.to_string()) {
+            result.push(package.to_string());
+        }
+        Ok(())
+    }
+
+    let mut result: Vec<String> = vec![];
+    // Sorted for the cycle reported to be the same on every run
+    let mut packages = graph.keys().collect::<Vec<_>>();
+    packages.sort();
+    for package in packages {
+        topo_sort_rec(graph, package, &mut vec![], &mut result)?;
+    }
+
+    Ok(result)
+}
+
+/// The chain of dependent projects taking the longest to build, each project
+/// weighing its duration in `durations` (none if missing), from the first
+/// project to build to the last. `order` is the order of [`topo_sort`].
+pub fn critical_path(
+    graph: &Graph,
+    order: &[String],
+    durations: &HashMap<String, Duration>,
+) -> Vec<String> {
+    // The longest chain ending with each project, and the dependency it
+    // comes from
+    let mut longest: HashMap<&str, (Duration, Option<&str>)> = HashMap::new();
+    for name in order {
+        let previous = graph[name]
+            .iter()
+            .filter_map(|dependency| Some((longest.get(dependency.as_str())?.0, dependency)))
+            .max_by_key(|(duration, _)| *duration);
+        let own = durations.get(name).copied().unwrap_or_default();
+        let (before, previous) = match previous {
+            Some((duration, dependency)) => (duration, Some(dependency.as_str())),
+            None => (Duration::default(), None),
+        };
+        longest.insert(name, (before + own, previous));
+    }
+    // Walk back from the end of the longest chain, the first in order on ties
+    let mut last = order
+        .iter()
+        .map(String::as_str)
+        .rev()
+        .max_by_key(|name| longest[name].0);
+    let mut path = vec![];
+    while let Some(name) = last {
+        path.push(name.to_string());
+        last = longest[name].1;
+    }
+    path.reverse();
+    path
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ecosystem::Dependency;
+
+    fn manifest(name: &str, dependencies: &[(&str, DependencyKind)]) -> Manifest {
+        Manifest {
+            name: String::from(name),
+            version: None,
+            dependencies: dependencies
+                .iter()
+                .map(|(name, kind)| Dependency {
+                    name: String::from(*name),
+                    requirement: String::from("*"),
+                    kind: *kind,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_graph() {
+        use DependencyKind::*;
+        let manifests = vec![
+            manifest("a", &[("lodash", Normal)]),
+            manifest("b", &[("a", Normal)]),
+            manifest("c", &[("a", Peer), ("a", Dev), ("b", Optional)]),
+        ];
+        let graph = build_dependency_graph(&manifests, &[Normal, Dev, Peer, Optional]).unwrap();
+        assert_eq!(graph["a"], Vec::<String>::new());
+        assert_eq!(graph["b"], vec!["a"]);
+        let mut c = graph["c"].clone();
+        c.sort();
+        assert_eq!(c, vec!["a", "b"]);
+        assert_eq!(topo_sort(&graph).unwrap(), vec!["a", "b", "c"]);
+
+        let graph = build_dependency_graph(&manifests, &[Normal]).unwrap();
+        assert_eq!(graph["c"], Vec::<String>::new());
+
+        // A peer dependency back on a dependent
+        let manifests = vec![
+            manifest("a", &[("b", Peer)]),
+            manifest("b", &[("a", Normal)]),
+        ];
+        let graph = build_dependency_graph(&manifests, &[Normal, Peer]).unwrap();
+        let error = topo_sort(&graph).unwrap_err().to_string();
+        assert!(error.contains("dependency cycle a -> b -> a"), "{}", error);
+        let graph = build_dependency_graph(&manifests, &[Normal]).unwrap();
+        assert_eq!(topo_sort(&graph).unwrap(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_critical_path() {
+        // a -> b -> d and c -> d, c being slower than a and b together
+        let graph = vec![
+            (String::from("a"), vec![]),
+            (String::from("b"), vec![String::from("a")]),
+            (String::from("c"), vec![]),
+            (
+                String::from("d"),
+                vec![String::from("b"), String::from("c")],
+            ),
+        ]
+        .into_iter()
+        .collect::<Graph>();
+        let order = ["a", "b", "c", "d"].map(String::from);
+        let seconds = |durations: &[(&str, u64)]| {
+            durations
+                .iter()
+                .map(|(name, seconds)| (name.to_string(), Duration::from_secs(*seconds)))
+                .collect::<HashMap<String, Duration>>()
+        };
+        let durations = seconds(&[("a", 2), ("b", 2), ("c", 5), ("d", 1)]);
+        assert_eq!(critical_path(&graph, &order, &durations), vec!["c", "d"]);
+        let durations = seconds(&[("a", 2), ("b", 4), ("c", 5), ("d", 1)]);
+        assert_eq!(
+            critical_path(&graph, &order, &durations),
+            vec!["a", "b", "d"]
+        );
+    }
+}
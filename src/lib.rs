@@ -0,0 +1,505 @@
+//! `integ` integrates a system of interdependent projects: it clones them,
+//! builds them in dependency order and installs the package generated for
+//! each project in its dependents. The package managers of the projects are
+//! abstracted as [`ecosystem::Ecosystem`]s.
+//!
+//! The pipeline is driven by an [`Integration`], each stage being callable
+//! independently:
+//!
+//! ```no_run
+//! use integ::{config::load_config_file, Integration};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let config = load_config_file(std::path::Path::new("integ.yaml"))?;
+//! let integration = Integration::new(config, "/tmp/integ").keep_going(true);
+//! integration.preflight()?;
+//! integration.retrieve()?;
+//! let resolution = integration.resolve()?;
+//! println!("build order: {:?}", resolution.order);
+//! integration.check_versions(&resolution)?;
+//! let report = integration.build(&resolution)?;
+//! print!("{}", integ::format_summary(&report));
+//! # Ok(())
+//! # }
+//! ```
+extern crate serde;
+
+mod build;
+mod clean;
+pub mod command;
+pub mod config;
+pub mod ecosystem;
+pub mod event;
+pub mod graph;
+mod preprocess;
+mod progress;
+pub mod registry;
+mod retrieve;
+mod retry;
+mod status;
+pub mod validate;
+pub mod version;
+
+pub use build::{format_summary, format_timings, Action, BuildReport, BuildStatus, Timings};
+pub use clean::CleanLevel;
+pub use retrieve::Checkout;
+pub use status::{Changes, ProjectStatus, Source};
+
+use anyhow::{Context, Result};
+use config::{Config, EcosystemKind, Entry, Lockfile, Repo, VersionCheck};
+use ecosystem::{ecosystem, Manifest};
+use event::Event;
+use graph::Graph;
+use registry::Registry;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use version::Mismatch;
+
+// The package managers installing the projects from their lockfiles, for the
+// projects which can be looked at: retrieved or to copy from a local folder
+fn lockfile_requirements(config: &Config, folders: &[String]) -> Vec<command::Requirement> {
+    if config.lockfile != Lockfile::Preserve {
+        return vec![];
+    }
+    config
+        .repositories
+        .iter()
+        .zip(folders)
+        .flat_map(|(entry, folder)| {
+            let folder = match entry {
+                _ if Path::new(folder).exists() => folder.as_str(),
+                Entry::Folder(local) => local.path.as_str(),
+                Entry::Repo(_) => return vec![],
+            };
+            ecosystem(entry.ecosystem()).lockfile_requirements(folder)
+        })
+        .collect()
+}
+
+pub(crate) fn requirements(config: &Config, folders: &[String]) -> Vec<command::Requirement> {
+    let mut requirements = vec![];
+    let repos = config
+        .repositories
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::Repo(repo) => Some(repo),
+            Entry::Folder(_) => None,
+        })
+        .collect::<Vec<&Repo>>();
+    if !repos.is_empty() {
+        requirements.push(command::Requirement {
+            tool: "git",
+            args: &["--version"],
+            min_version: Some((2, 11, 0)),
+            reason: String::from("to clone repositories"),
+        });
+    }
+    if let Some(repo) = repos.iter().find(|repo| repo.lfs) {
+        requirements.push(command::Requirement {
+            tool: "git",
+            args: &["lfs", "version"],
+            min_version: Some((2, 0, 0)),
+            reason: format!("by {} (`lfs: true`)", repo.url),
+        });
+    }
+    for entry in &config.repositories {
+        for requirement in ecosystem(entry.ecosystem()).requirements() {
+            let known = requirements.iter().any(|r: &command::Requirement| {
+                r.tool == requirement.tool && r.args == requirement.args
+            });
+            if !known {
+                requirements.push(requirement);
+            }
+        }
+    }
+    for requirement in lockfile_requirements(config, folders) {
+        if !requirements.iter().any(|r| r.tool == requirement.tool) {
+            requirements.push(requirement);
+        }
+    }
+    requirements.push(command::Requirement {
+        tool: "bash",
+        args: &["--version"],
+        min_version: None,
+        reason: String::from("to run build commands"),
+    });
+    requirements
+}
+
+/// The projects of an integration once retrieved and linked together.
+#[derive(Debug)]
+pub struct Resolution {
+    /// The folder of each entry of the configuration, in the same order
+    pub folders: Vec<String>,
+    /// The manifest of each entry of the configuration, in the same order
+    pub manifests: Vec<Manifest>,
+    pub graph: Graph,
+    /// Project names in the order they are built
+    pub order: Vec<String>,
+}
+
+/// An integration of the projects listed in a [`Config`] into an output folder.
+pub struct Integration {
+    config: Config,
+    output_path: PathBuf,
+    cache: Option<PathBuf>,
+    keep_going: bool,
+    run_id: String,
+}
+
+impl Integration {
+    pub fn new(config: Config, output_path: impl Into<PathBuf>) -> Self {
+        let cache = config.cache.clone();
+        let run_id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default()
+            .to_string();
+        Integration {
+            config,
+            output_path: output_path.into(),
+            cache,
+            keep_going: false,
+            run_id,
+        }
+    }
+
+    /// Keep bare mirrors of the repositories in `cache`, overriding the
+    /// `cache` of the configuration.
+    pub fn cache(mut self, cache: impl Into<PathBuf>) -> Self {
+        self.cache = Some(cache.into());
+        self
+    }
+
+    /// Keep building the projects which do not depend on a failed one
+    /// instead of stopping at the first failure.
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
+    }
+
+    /// Identify the run in the stamped versions (the Unix time at which the
+    /// integration was created by default), e.g. with a CI build number.
+    pub fn run_id(mut self, run_id: impl Into<String>) -> Self {
+        self.run_id = run_id.into();
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn output_path(&self) -> &Path {
+        &self.output_path
+    }
+
+    /// The folder each entry of the configuration is retrieved into.
+    pub fn folders(&self) -> Vec<String> {
+        retrieve::folder_names(&self.output_path, &self.config.repositories)
+    }
+
+    /// Check that the tools needed by the integration are installed.
+    pub fn preflight(&self) -> Result<()> {
+        command::preflight(&requirements(&self.config, &self.folders()))
+    }
+
+    /// Clone or copy the projects which are not in the output folder yet.
+    pub fn retrieve(&self) -> Result<Vec<Checkout>> {
+        retrieve::retrieve_repositories(
+            &self.config.repositories,
+            &self.folders(),
+            self.cache.as_deref(),
+            &self.config.retry,
+            &self.config.timeouts,
+        )
+        .context("could not clone repositories")
+    }
+
+    /// Read the manifests of the retrieved projects and order them.
+    pub fn resolve(&self) -> Result<Resolution> {
+        let folders = self.folders();
+        let manifests = self
+            .config
+            .repositories
+            .iter()
+            .zip(&folders)
+            .map(|(entry, folder)| ecosystem(entry.ecosystem()).manifest(folder))
+            .collect::<Result<Vec<Manifest>>>()
+            .context("fail to parse package")?;
+        let graph = graph::build_dependency_graph(&manifests, &self.config.edges)
+            .context("fail to build dependency graph")?;
+        let order = graph::topo_sort(&graph).context("fail to order the builds")?;
+        Ok(Resolution {
+            folders,
+            manifests,
+            graph,
+            order,
+        })
+    }
+
+    /// The package built for each project so far, by project name.
+    pub fn artifacts(&self) -> HashMap<String, String> {
+        let progress_path = self.output_path.join("integ.progress");
+        progress::load_package_paths(&progress_path.to_string_lossy()).unwrap_or_default()
+    }
+
+    /// The state of each project of the configuration in the output folder,
+    /// in the order of the configuration.
+    pub fn status(&self) -> Vec<ProjectStatus> {
+        let artifacts = self.artifacts();
+        let failures_path = self.output_path.join("integ.failures");
+        let failures =
+            progress::load_failures(&failures_path.to_string_lossy()).unwrap_or_default();
+        // Staleness needs the whole graph, so every project retrieved
+        let plan = self
+            .resolve()
+            .ok()
+            .and_then(|resolution| self.plan(&resolution).ok())
+            .unwrap_or_default();
+        self.config
+            .repositories
+            .iter()
+            .zip(self.folders())
+            .map(|(entry, folder)| {
+                let retrieved = Path::new(&folder).exists();
+                let name = match retrieved {
+                    true => ecosystem(entry.ecosystem()).manifest(&folder).ok(),
+                    false => None,
+                }
+                .map_or(entry.folder_name().to_string(), |manifest| manifest.name);
+                let artifact = artifacts.get(&name).cloned();
+                let stale = plan
+                    .iter()
+                    .find(|(project, _)| *project == name)
+                    .map(|(_, action)| artifact.is_some() && *action == Action::Build);
+                ProjectStatus {
+                    source: status::source(entry, &folder, retrieved),
+                    changes: match (entry, retrieved) {
+                        (Entry::Repo(_), true) => status::changes(&folder),
+                        _ => None,
+                    },
+                    last_failure: failures.get(&name).cloned(),
+                    name,
+                    folder,
+                    retrieved,
+                    artifact,
+                    stale,
+                }
+            })
+            .collect()
+    }
+
+    /// What [`Integration::build`] would do with each project, in build order.
+    pub fn plan(&self, resolution: &Resolution) -> Result<Vec<(String, Action)>> {
+        build::plan(&self.output_path, &resolution.graph, &resolution.order)
+    }
+
+    /// Delete what `level` designates for the projects named in `projects`
+    /// by folder or package name, or for all of them if empty, and return
+    /// the paths removed. With `force`, the whole output path is deleted
+    /// instead, which requires `CleanLevel::All` and no project.
+    pub fn clean(
+        &self,
+        level: CleanLevel,
+        projects: &[String],
+        force: bool,
+    ) -> Result<Vec<String>> {
+        clean::clean(
+            &self.output_path,
+            &self.config.repositories,
+            &self.folders(),
+            projects,
+            level,
+            force,
+        )
+    }
+
+    /// Check the versions of the integrated packages against the ranges
+    /// declared by their dependents, as per the `version_check` policy:
+    /// mismatches are returned (and printed as warnings) or are an error.
+    pub fn check_versions(&self, resolution: &Resolution) -> Result<Vec<Mismatch>> {
+        if self.config.version_check == VersionCheck::Ignore {
+            return Ok(vec![]);
+        }
+        let (mismatches, unchecked) = version::check_versions(
+            &self.config.repositories,
+            &resolution.manifests,
+            &self.config.edges,
+        );
+        for requirement in &unchecked {
+            event::warn(format_args!(
+                "warning: {} requires {} {}, which cannot be checked against {}",
+                requirement.dependent,
+                requirement.dependency,
+                requirement.requirement,
+                requirement.version
+            ));
+        }
+        if mismatches.is_empty() {
+            return Ok(mismatches);
+        }
+        let list = mismatches
+            .iter()
+            .map(|mismatch| mismatch.to_string())
+            .collect::<Vec<String>>()
+            .join("\n  ");
+        if self.config.version_check == VersionCheck::Error {
+            return Err(anyhow::anyhow!("incompatible versions:\n  {}", list));
+        }
+        event::warn(format_args!("warning: incompatible versions:\n  {}", list));
+        Ok(mismatches)
+    }
+
+    /// Build and package the projects, skipping the ones whose package is
+    /// more recent than the packages of their dependencies.
+    pub fn build(&self, resolution: &Resolution) -> Result<BuildReport> {
+        self.build_after(resolution, &[])
+    }
+
+    // Build the projects, with how long the `checkouts` of this run took in
+    // the timings the critical path is found from
+    fn build_after(&self, resolution: &Resolution, checkouts: &[Checkout]) -> Result<BuildReport> {
+        // The lockfiles of the projects cloned since the preflight are only
+        // known now
+        command::preflight(&lockfile_requirements(&self.config, &resolution.folders))?;
+        let projects = build::coalesce_projects(
+            &self.config.repositories,
+            &resolution.folders,
+            &resolution.manifests,
+        );
+        let options = build::BuildOptions {
+            keep_going: self.keep_going,
+            retry: &self.config.retry,
+            timeouts: &self.config.timeouts,
+            run_id: Some(self.run_id.as_str()).filter(|_| self.config.stamp),
+            publish: self.config.publish.as_ref(),
+            lockfile: self.config.lockfile,
+        };
+        let mut report = build::build_all(
+            &self.output_path,
+            &projects,
+            &resolution.graph,
+            &resolution.order,
+            &options,
+        )
+        .context("Build failed")?;
+        for (checkout, manifest) in checkouts.iter().zip(&resolution.manifests) {
+            if checkout.fetched {
+                let timings = report.timings.entry(manifest.name.clone()).or_default();
+                timings.retrieve = Some(checkout.duration);
+            }
+        }
+        report.update_critical_path(&resolution.graph);
+        Ok(report)
+    }
+
+    /// A registry serving the npm packages built so far, with their
+    /// dependencies on each other pinned to the versions served.
+    pub fn registry(&self, upstream: &str) -> Result<Registry> {
+        let artifacts = self.artifacts();
+        if artifacts.is_empty() {
+            return Err(anyhow::anyhow!("nothing has been built yet"));
+        }
+        let resolution = self.resolve()?;
+        let mut packages = vec![];
+        for ((entry, folder), manifest) in self
+            .config
+            .repositories
+            .iter()
+            .zip(&resolution.folders)
+            .zip(&resolution.manifests)
+        {
+            if entry.ecosystem() != EcosystemKind::Npm {
+                continue;
+            }
+            if let Some(tarball) = artifacts.get(&manifest.name) {
+                let package_json_path = Path::new(folder).join("package.json");
+                let package_json: Value = serde_json::from_str(
+                    &std::fs::read_to_string(&package_json_path).with_context(|| {
+                        format!("reading {}", package_json_path.to_string_lossy())
+                    })?,
+                )
+                .with_context(|| {
+                    format!("Trying to parse {}", package_json_path.to_string_lossy())
+                })?;
+                packages.push((package_json, tarball));
+            }
+        }
+        // The dependencies patched to tarball paths are served at their version
+        let versions = packages
+            .iter()
+            .filter_map(|(package_json, _)| {
+                Some((
+                    package_json["name"].as_str()?.to_string(),
+                    package_json["version"].clone(),
+                ))
+            })
+            .collect::<Vec<(String, Value)>>();
+        let tag = match &self.config.publish {
+            Some(publish) => publish.tag.clone(),
+            None => config::default_dist_tag(),
+        };
+        let mut registry = Registry::new(upstream, tag);
+        for (mut package_json, tarball) in packages {
+            for section in [
+                "dependencies",
+                "devDependencies",
+                "optionalDependencies",
+                "peerDependencies",
+            ] {
+                if let Some(Value::Object(dependencies)) = package_json.get_mut(section) {
+                    for (name, version) in &versions {
+                        if let Some(requirement) = dependencies.get_mut(name) {
+                            *requirement = version.clone();
+                        }
+                    }
+                }
+            }
+            registry.add(package_json, tarball)?;
+        }
+        Ok(registry)
+    }
+
+    /// Run all the stages in turn.
+    pub fn run(&self) -> Result<BuildReport> {
+        event::emit(Event::RunStarted {
+            projects: self
+                .config
+                .repositories
+                .iter()
+                .map(|entry| entry.folder_name().to_string())
+                .collect(),
+        });
+        let start = Instant::now();
+        let result = self.run_stages();
+        let success = match &result {
+            Ok(report) => report.is_ok(),
+            Err(_) => false,
+        };
+        event::emit(Event::RunFinished {
+            success,
+            duration_ms: start.elapsed().as_millis(),
+            error: result.as_ref().err().map(|error| format!("{:#}", error)),
+        });
+        result
+    }
+
+    fn run_stages(&self) -> Result<BuildReport> {
+        event::stage("preflight", || self.preflight())?;
+        let checkouts = event::stage("retrieve", || self.retrieve())?;
+        let resolution = event::stage("resolve", || self.resolve())?;
+        event::emit(Event::Resolved {
+            order: resolution.order.clone(),
+            folders: resolution
+                .manifests
+                .iter()
+                .map(|manifest| manifest.name.clone())
+                .zip(resolution.folders.iter().cloned())
+                .collect(),
+        });
+        event::stage("check_versions", || self.check_versions(&resolution))?;
+        event::stage("build", || self.build_after(&resolution, &checkouts))
+    }
+}
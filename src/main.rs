@@ -1,65 +1,132 @@
-extern crate serde;
 use anyhow::{Context, Result};
-use io::prelude::*;
-use pathdiff;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use integ::config::{json_schema, load_config_file, Config, Override};
+use integ::event::{say, set_output_format, OutputFormat};
+use integ::validate::{validate_file, Severity};
+use integ::{format_summary, format_timings, Action, CleanLevel, Integration};
 use std::error::Error;
-use std::format;
-use std::fs;
-use std::io::{self, ErrorKind};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
+use structopt::clap::{Error as ClapError, ErrorKind};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
+#[structopt(about = "Integrate a system of interdependent projects")]
 struct Opt {
-    #[structopt(short, long, parse(try_from_str = file_exists))]
-    config: PathBuf,
-
-    #[structopt(short, long, parse(try_from_str = file_exists))]
-    output_path: PathBuf,
-}
-
-#[derive(Deserialize, Debug)]
-struct Repo {
-    url: String,
-    branch: String,
-    build: Vec<String>,
-}
-
-#[derive(Deserialize, Debug)]
-struct Folder {
-    path: String,
-    build: Vec<String>,
+    /// Required by all the commands but `schema`
+    #[structopt(short, long, global = true, parse(try_from_str = file_exists))]
+    config: Option<PathBuf>,
+
+    /// Folder the projects are retrieved and built in, created if missing
+    #[structopt(short, long, global = true)]
+    output_path: Option<PathBuf>,
+
+    /// Folder holding the bare mirrors of the repositories (overrides `cache` in the config)
+    #[structopt(long, global = true)]
+    cache: Option<PathBuf>,
+
+    /// Keep building the projects which do not depend on a failed one
+    #[structopt(long, global = true)]
+    keep_going: bool,
+
+    /// Identifies the run in the versions stamped with `stamp: true` (defaults to the Unix time)
+    #[structopt(long, global = true)]
+    run_id: Option<String>,
+
+    /// Check out another branch of a repository, as `<folder name>=<branch>`
+    #[structopt(
+        long = "branch",
+        global = true,
+        value_name = "name=branch",
+        number_of_values = 1,
+        parse(try_from_str = parse_branch)
+    )]
+    branches: Vec<Override>,
+
+    /// Copy a project from a local folder instead, as `<folder name>=<path>`
+    #[structopt(
+        long = "path",
+        global = true,
+        value_name = "name=path",
+        number_of_values = 1,
+        parse(try_from_str = parse_path)
+    )]
+    paths: Vec<Override>,
+
+    /// Print the progress of `build` and `restore` as JSON events, one per line
+    #[structopt(
+        long,
+        global = true,
+        default_value = "human",
+        possible_values = &OutputFormat::NAMES
+    )]
+    output_format: OutputFormat,
+
+    /// `build` if omitted
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-fn default_workers() -> usize {
-    1
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Retrieve the projects and build them in dependency order
+    Build,
+    /// Print the build order and whether each project would be rebuilt
+    Plan,
+    /// Print the integrated dependencies of each project
+    Graph {
+        /// In the Graphviz format
+        #[structopt(long)]
+        dot: bool,
+    },
+    /// Show the source, checkout, artifact and last failure of each project
+    Status,
+    /// Delete the artifacts, dependencies or folders of projects, or all integ created
+    Clean {
+        /// What to delete
+        #[structopt(long, default_value = "artifacts", possible_values = &CleanLevel::NAMES)]
+        level: CleanLevel,
+
+        /// Delete the whole output path, whatever it contains, with `--level all`
+        #[structopt(long)]
+        force: bool,
+
+        /// Folder or package names of the projects to clean, all of them if omitted
+        projects: Vec<String>,
+    },
+    /// Check the configuration file and report all its problems
+    Validate,
+    /// Clone or copy the projects missing from the output path, without building them
+    Restore,
+    /// Serve the npm packages built in the output path as a registry
+    Serve {
+        #[structopt(long, default_value = "127.0.0.1:4873")]
+        address: String,
+
+        /// Registry the requests for the other packages are redirected to
+        #[structopt(long, default_value = "https://registry.npmjs.org/")]
+        upstream: String,
+    },
+    /// Print the JSON Schema of the configuration file
+    Schema,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(untagged)]
-enum Entry {
-    Repo(Repo),
-    Folder(Folder),
+// Split a `name=value` command line override
+fn split_override(value: &str) -> Result<(String, String), Box<dyn Error>> {
+    match value.split_once('=') {
+        Some((name, value)) if !name.is_empty() && !value.is_empty() => {
+            Ok((name.to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected <folder name>=<value>, got `{}`", value).into()),
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct Config {
-    repositories: Vec<Entry>,
-    #[serde(default = "default_workers")]
-    workers: usize,
+fn parse_branch(value: &str) -> Result<Override, Box<dyn Error>> {
+    let (name, branch) = split_override(value)?;
+    Ok(Override::Branch { name, branch })
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Package {
-    name: String,
-    #[serde(default)]
-    dependencies: HashMap<String, String>,
-    #[serde(default, rename = "devDependencies")]
-    dev_dependencies: HashMap<String, String>,
+fn parse_path(value: &str) -> Result<Override, Box<dyn Error>> {
+    let (name, path) = split_override(value)?;
+    Ok(Override::Path { name, path })
 }
 
 fn file_exists(path: &str) -> Result<PathBuf, Box<dyn Error>> {
@@ -71,416 +138,189 @@ fn file_exists(path: &str) -> Result<PathBuf, Box<dyn Error>> {
     }
 }
 
-fn load_config(config_yaml: &str) -> serde_yaml::Result<Config> {
-    serde_yaml::from_str(config_yaml)
-}
-
-fn get_folder_names(opt: &Opt, config: &Config) -> Vec<String> {
-    config
-        .repositories
-        .iter()
-        .map(|repository| match repository {
-            Entry::Repo(repo) => repo
-                .url
-                .split('/')
-                .last()
-                .unwrap()
-                .split('.')
-                .nth(0)
-                .unwrap(),
-            Entry::Folder(folder) => folder.path.split('/').last().unwrap(),
-        })
-        .map(|f| {
-            format!(
-                "{}",
-                PathBuf::from(opt.output_path.clone())
-                    .join(f)
-                    .to_string_lossy()
-            )
-        })
-        .collect::<_>()
-}
-
-fn is_rsync_present() -> bool {
-    return match Command::new("rsync")
-        .arg("--help")
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()
-    {
-        Ok(_) => true,
-        Err(_) => false,
-    };
-}
-
-fn clone_repository(repository: &Repo, folder: &str) -> Result<()> {
-    println!("cloning {}", repository.url);
-    let output = Command::new("git")
-        .arg("clone")
-        .arg("--branch")
-        .arg(repository.branch.to_owned())
-        .arg("--depth")
-        .arg("1")
-        .arg(repository.url.to_owned())
-        .arg(&folder)
-        .output()
-        .expect(&format!("fail to clone {}", repository.url));
-
-    if !output.status.success() {
-        // If clone failed, print the command's standard output
-        let error_message = String::from_utf8(output.stderr).unwrap();
-        return Err(io::Error::new(ErrorKind::Other, error_message.to_owned()))
-            .with_context(|| format!("Trying to clone {}", repository.url))?;
+// Print the problems of the configuration, failing if any is an error
+fn check_config(path: &Path) -> Result<()> {
+    let problems = validate_file(path)?;
+    for (file, problem) in &problems {
+        eprintln!("{}:{}", file.to_string_lossy(), problem);
     }
-    Ok(())
-}
-
-fn copy_folder(repository: &Folder, folder: &str) -> Result<()> {
-    let output = if is_rsync_present() {
-        println!("syncing {} to {}", repository.path, folder);
-        Command::new("rsync")
-            .arg("-av")
-            .arg(format!("{}/", &repository.path))
-            .arg(&folder)
-            .arg("--exclude=node_modules")
-            .output()
-            .expect(&format!("fail to sync {} to {}", repository.path, folder))
-    } else {
-        println!("copying {} to {}", repository.path, folder);
-        Command::new("cp")
-            .arg("-r")
-            .arg(&repository.path)
-            .arg(&folder)
-            .output()
-            .expect(&format!("fail to copy {} to {}", repository.path, folder))
-    };
-    if !output.status.success() {
-        // If clone failed, print the command's standard output
-        let error_message = String::from_utf8(output.stderr).unwrap();
-        return Err(io::Error::new(ErrorKind::Other, error_message.to_owned()))
-            .with_context(|| format!("Trying to sync/copy {}", repository.path))?;
+    let errors = problems
+        .iter()
+        .filter(|(_, problem)| problem.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(anyhow::anyhow!(
+            "invalid configuration `{}`: {} error(s)",
+            path.to_string_lossy(),
+            errors
+        ));
     }
     Ok(())
 }
 
-fn retrieve_repositories(repositories: &Vec<Entry>, folders: &Vec<String>) -> Result<Vec<String>> {
-    let mut paths = vec![];
-    assert_eq!(repositories.len(), folders.len());
-    for (index, repository) in repositories.iter().enumerate() {
-        if PathBuf::from(folders[index].clone()).exists() {
-            println!("{} already exists, skipping", folders[index]);
-            continue;
-        }
-        match repository {
-            Entry::Repo(repo) => clone_repository(repo, &folders[index]),
-            Entry::Folder(folder) => copy_folder(folder, &folders[index]),
-        }?;
-        paths.push(String::from(&folders[index]));
-    }
-
-    Ok(paths)
+fn read_config(path: &Path) -> Result<Config> {
+    check_config(path)?;
+    load_config_file(path)
 }
 
-fn parse_package(folders: &Vec<String>) -> Result<Vec<Package>> {
-    let mut packages = vec![];
-    for folder in folders {
-        let package_json_path = PathBuf::from(folder).join("package.json");
-        let package_json = fs::read_to_string(package_json_path.clone())
-            .with_context(|| format!("reading {}", package_json_path.to_string_lossy()))?;
-        let package = serde_json::from_str(&package_json)
-            .with_context(|| format!("Trying to parse {}", package_json_path.to_string_lossy()))?;
-        packages.push(package);
+// The value of an option only required by some commands
+fn required<'a>(value: &'a Option<PathBuf>, argument: &str) -> &'a PathBuf {
+    match value {
+        Some(value) => value,
+        None => ClapError::with_description(
+            &format!(
+                "The following required arguments were not provided:\n    {}",
+                argument
+            ),
+            ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
     }
-    Ok(packages)
 }
 
-type Graph = HashMap<String, Vec<String>>;
+fn build(integration: &Integration, keep_going: bool, overrides: &[&Override]) -> Result<()> {
+    let report = integration.run()?;
 
-fn build_dependency_graph(packages: &Vec<Package>) -> Result<Graph> {
-    let mut graph = Graph::new();
-    let names: Vec<String> = packages
-        .iter()
-        .map(|package| package.name.clone())
-        .collect::<_>();
-    for package in packages {
-        let dependency_names = package
-            .dependencies
-            .iter()
-            .chain(package.dev_dependencies.iter())
-            .map(|(k, _)| k.clone())
-            .filter(|n| names.contains(n))
-            .collect::<Vec<String>>();
-        graph.insert(package.name.clone(), dependency_names);
+    say!("{}", format_timings(&report).trim_end());
+    if keep_going {
+        say!("{}", format_summary(&report).trim_end());
     }
-
-    Ok(graph)
-}
-
-fn topo_sort(graph: &Graph) -> Vec<String> {
-    fn topo_sort_rec(graph: &Graph, package: &str, result: &mut Vec<String>) {
-        let dependencies = graph.get(package).unwrap();
-        let unresolved_dependencies = dependencies
-            .iter()
-            .filter(|p| !result.contains(p))
-            .map(|s| s.clone())
-            .collect::<Vec<String>>();
-        for ud in unresolved_dependencies {
-            topo_sort_rec(graph, &ud, result);
-        }
-        if !result.contains(&package.to_string()) {
-            result.push(package.to_string());
+    if !overrides.is_empty() {
+        say!("overrides:");
+        for change in overrides {
+            say!("  {}", change);
         }
     }
-
-    let mut result: Vec<String> = vec![];
-    for (package, _) in graph.into_iter() {
-        topo_sort_rec(graph, package, &mut result);
+    let unsuccessful = report.iter().filter(|(_, status)| !status.is_ok()).count();
+    if unsuccessful > 0 {
+        return Err(anyhow::anyhow!(
+            "{} project(s) failed or were blocked",
+            unsuccessful
+        ));
     }
-
-    return result;
+    say!("All builds successful!");
+    Ok(())
 }
 
-fn patch_dependencies(folder: &str, dependencies: &Vec<(String, String)>) -> Result<()> {
-    for (dependency_name, package_path) in dependencies {
-        // sed -e 's#"@ifabric/common-logger": "[^"]*"#"@ifabric/common-logger": "mypackage"#' package.json
-        let relative_package_path = pathdiff::diff_paths(package_path, folder).unwrap();
-        let s_expression = format!(
-            r#"s%"{}": "[^"]*"%"{}": "{}"%"#,
-            dependency_name,
-            dependency_name,
-            relative_package_path.to_string_lossy(),
-        );
-        let dependency_output = Command::new("sed")
-            .current_dir(folder)
-            .arg("-i")
-            .arg("-e")
-            .arg(s_expression)
-            .arg("package.json")
-            .output()
-            .expect(&format!(
-                "failed to patch {} in {}",
-                dependency_name, folder
-            ));
-
-        if !dependency_output.status.success() {
-            let std_output = String::from_utf8(dependency_output.stdout).unwrap();
-            eprintln!("{}", std_output);
-            let error_message = String::from_utf8(dependency_output.stderr).unwrap();
-            return Err(io::Error::new(ErrorKind::Other, error_message.to_owned()))
-                .with_context(|| format!("Trying to patch {} for {}", dependency_name, folder))?;
-        }
+fn plan(integration: &Integration) -> Result<()> {
+    integration.preflight()?;
+    integration.retrieve()?;
+    let resolution = integration.resolve()?;
+    for (index, (name, action)) in integration.plan(&resolution)?.iter().enumerate() {
+        let action = match action {
+            Action::Build => "build",
+            Action::Reuse => "reuse",
+        };
+        println!("{:>3}. {:5}  {}", index + 1, action, name);
     }
     Ok(())
 }
 
-fn build_and_package(
-    repository: &Entry,
-    folder: &str,
-    dependencies: &Vec<(String, String)>,
-) -> Result<String> {
-    // Patch dependencies
-    patch_dependencies(folder, dependencies)?;
-    // Clean up the folder
-    std::fs::remove_file(String::from(
-        PathBuf::from(folder)
-            .join("package-lock.json")
-            .to_string_lossy(),
-    ))
-    .ok();
-    let node_module_path =
-        String::from(PathBuf::from(folder).join("node_modules").to_string_lossy());
-    std::fs::create_dir(&node_module_path).unwrap_or(());
-    std::fs::remove_dir_all(&node_module_path)?;
-    // Install dependencies
-    println!("Installing dependencies for {}", folder);
-    let dependency_output = Command::new("npm")
-        .current_dir(folder)
-        .arg("install")
-        .output()
-        .expect(&format!("failed to install dependencies in {}", folder));
-
-    if !dependency_output.status.success() {
-        let error_message = String::from_utf8(dependency_output.stderr).unwrap();
-        return Err(io::Error::new(ErrorKind::Other, error_message.to_owned()))
-            .with_context(|| format!("Trying to install dependencies in {}", folder))?;
-    }
-    // Run the build
-    println!("Building {}", folder);
-    let build_commands = match repository {
-        Entry::Repo(r) => &r.build,
-        Entry::Folder(f) => &f.build,
-    };
-    for command in build_commands.iter() {
-        let build_output = Command::new("bash")
-            .current_dir(folder)
-            .arg("-c")
-            .arg(command)
-            .output()
-            .expect(&format!("{}: failed build command {}", folder, command));
-
-        if !build_output.status.success() {
-            let std_output = String::from_utf8(build_output.stdout).unwrap();
-            eprintln!("{}", std_output);
-            let error_message = String::from_utf8(build_output.stderr).unwrap();
-            return Err(io::Error::new(ErrorKind::Other, error_message.to_owned()))
-                .with_context(|| format!("Trying to build {} with {}", folder, command))?;
-        }
-    }
-    // Create the package
-    println!("Packaging {}", folder);
-    let package_output = Command::new("npm")
-        .current_dir(folder)
-        .arg("pack")
-        .output()
-        .expect(&format!("failed to package {}", folder));
-
-    if !package_output.status.success() {
-        let std_output = String::from_utf8(package_output.stdout).unwrap();
-        eprintln!("{}", std_output);
-        let error_message = String::from_utf8(package_output.stderr).unwrap();
-        return Err(io::Error::new(ErrorKind::Other, error_message.to_owned()))
-            .with_context(|| format!("Trying to pack {}", folder))?;
+fn graph(integration: &Integration, dot: bool) -> Result<()> {
+    integration.preflight()?;
+    integration.retrieve()?;
+    let resolution = integration.resolve()?;
+    if dot {
+        println!("digraph integ {{");
     }
-    // Why do I need to create this temporary variable????
-    let x = String::from_utf8(package_output.stdout).unwrap();
-    let package_file = x.split('\n').filter(|s| s.len() > 0).last().unwrap();
-    println!("{} generated", package_file);
-    Ok(String::from(
-        PathBuf::from(folder).join(package_file).to_string_lossy(),
-    ))
-}
-
-#[derive(Debug)]
-struct Project<'a> {
-    name: String,
-    package: &'a Package,
-    repo: &'a Entry,
-    folder: &'a String,
-}
-
-fn coalesce_projects<'a>(
-    entries: &'a Vec<Entry>,
-    folders: &'a Vec<String>,
-    packages: &'a Vec<Package>,
-) -> HashMap<String, Project<'a>> {
-    assert_eq!(entries.len(), folders.len());
-    assert_eq!(entries.len(), packages.len());
-    entries
-        .iter()
-        .zip(folders)
-        .zip(packages)
-        .map(|((repo, folder), package)| Project {
-            name: package.name.clone(),
-            package,
-            repo,
-            folder,
-        })
-        .fold(HashMap::new(), |mut acc, project| {
-            acc.insert(project.name.clone(), project);
-            return acc;
-        })
-}
-
-fn build_all(
-    opt: &Opt,
-    projects: &HashMap<String, Project>,
-    graph: &Graph,
-    order: &Vec<String>,
-) -> Result<()> {
-    let progress_path = PathBuf::from(opt.output_path.clone()).join("integ.progress");
-    let progress_file = progress_path.to_string_lossy();
-    let mut package_paths = load_package_paths(&progress_file).unwrap_or(HashMap::new());
-    for project_name in order {
-        let dependency_packages = graph
-            .get(project_name)
-            .unwrap()
-            .iter()
-            .map(|d| (d.clone(), package_paths.get(d).unwrap().clone()))
-            .collect::<Vec<(String, String)>>();
-
-        if let Some(project_package_file) = package_paths.get(project_name) {
-            // Here we are going to check if the project has a package file that is
-            // more recent that the oldest package files of its dependencies.
-            // If this is the case, it means it should be rebuilt.
-            let mut sorted_dependency_time = dependency_packages
-                .iter()
-                // No error handling on the metadata retrieval functions here to makes things simple.
-                // We assume the package have been created correctly.
-                .map(|(_, filepath)| {
-                    fs::metadata(filepath)
-                        .unwrap()
-                        .modified()
-                        .unwrap_or(UNIX_EPOCH)
-                })
-                .collect::<Vec<SystemTime>>();
-            // We get an array of dependency packages creation/modification time
-            sorted_dependency_time.sort_by(|a, b| b.partial_cmp(a).unwrap()); // oldest first
-            if sorted_dependency_time.len() == 0
-                || sorted_dependency_time[0]
-                    < fs::metadata(project_package_file)
-                        .unwrap()
-                        .modified()
-                        .unwrap()
-            {
-                continue;
+    for name in &resolution.order {
+        let dependencies = &resolution.graph[name];
+        if dot {
+            println!("  \"{}\";", name);
+            for dependency in dependencies {
+                println!("  \"{}\" -> \"{}\";", name, dependency);
             }
+        } else if dependencies.is_empty() {
+            println!("{}", name);
+        } else {
+            println!("{} -> {}", name, dependencies.join(", "));
         }
-        let project = projects.get(project_name).unwrap();
-        let package_path = build_and_package(project.repo, project.folder, &dependency_packages)?;
-        package_paths.insert(project_name.clone(), package_path.clone());
-        dump_package_paths(&progress_file, &package_paths)
-            .with_context(|| format!("Fail while trying to save progress in {}", progress_file))?;
+    }
+    if dot {
+        println!("}}");
     }
     Ok(())
 }
 
-fn dump_package_paths(
-    progress_filename: &str,
-    package_paths: &HashMap<String, String>,
-) -> Result<()> {
-    let mut file = std::fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(progress_filename)?;
-    for (project_name, package_path) in package_paths {
-        writeln!(file, "{} {}", project_name, package_path)?;
+fn status(integration: &Integration) -> Result<()> {
+    for project in integration.status() {
+        print!("{}", project);
     }
     Ok(())
 }
 
-fn load_package_paths(progress_filename: &str) -> Result<HashMap<String, String>> {
-    let result = std::fs::read_to_string(progress_filename)
-        .with_context(|| format!(""))?
-        .split('\n')
-        .into_iter()
-        .map(|line| line.split(' ').collect::<Vec<&str>>())
-        .filter(|line| line.len() == 2)
-        // ignore entry for which package file do not exists
-        .filter(|line| PathBuf::from(line[1].clone()).exists())
-        .map(|line| (String::from(line[0]), String::from(line[1])))
-        .collect();
+// The overrides of the configuration given on the command line
+fn overrides(opt: &Opt) -> Vec<&Override> {
+    opt.branches.iter().chain(&opt.paths).collect()
+}
 
-    Ok(result)
+// The integration of the configuration into the output path, which the
+// commands only reading it do not create
+fn integration(opt: &Opt, create_output_path: bool) -> Result<Integration> {
+    let config_path = required(&opt.config, "--config <config>");
+    let output_path = required(&opt.output_path, "--output-path <output-path>");
+    let mut config = read_config(config_path)?;
+    for change in overrides(opt) {
+        config
+            .apply(change)
+            .with_context(|| format!("could not apply `{}`", change))?;
+    }
+    if create_output_path {
+        std::fs::create_dir_all(output_path)
+            .with_context(|| format!("could not create `{}`", output_path.to_string_lossy()))?;
+    }
+    let mut integration = Integration::new(config, output_path).keep_going(opt.keep_going);
+    if let Some(cache) = &opt.cache {
+        integration = integration.cache(cache);
+    }
+    if let Some(run_id) = &opt.run_id {
+        integration = integration.run_id(run_id);
+    }
+    Ok(integration)
 }
 
 fn main() -> Result<()> {
-    let opt = Opt::from_args();
-    let config_file = std::fs::read_to_string(&opt.config)
-        .with_context(|| format!("could not read file `{}`", opt.config.to_string_lossy()))?;
-    let config = load_config(&config_file)
-        .with_context(|| format!("could not read file `{}`", opt.config.to_string_lossy()))?;
-    let folders = get_folder_names(&opt, &config);
-    retrieve_repositories(&config.repositories, &folders)
-        .with_context(|| format!("could not clone repositories"))?;
-    let packages = parse_package(&folders).with_context(|| format!("fail to parse package"))?;
-    let projects = coalesce_projects(&config.repositories, &folders, &packages);
-    let graph = build_dependency_graph(&packages)
-        .with_context(|| format!("fail to build dependency graph"))?;
-    let topological_order = topo_sort(&graph);
-
-    build_all(&opt, &projects, &graph, &topological_order)
-        .with_context(|| format!("Build failed"))?;
-
-    println!("All builds successful!");
-    Ok(())
+    let mut opt = Opt::from_args();
+    set_output_format(opt.output_format);
+    match opt.command.take().unwrap_or(Command::Build) {
+        Command::Build => build(&integration(&opt, true)?, opt.keep_going, &overrides(&opt)),
+        Command::Plan => plan(&integration(&opt, true)?),
+        Command::Graph { dot } => graph(&integration(&opt, true)?, dot),
+        Command::Status => status(&integration(&opt, false)?),
+        Command::Clean {
+            level,
+            force,
+            projects,
+        } => {
+            for path in integration(&opt, false)?.clean(level, &projects, force)? {
+                println!("removed {}", path);
+            }
+            Ok(())
+        }
+        Command::Validate => {
+            let config_path = required(&opt.config, "--config <config>");
+            read_config(config_path)?;
+            println!("{} is valid", config_path.to_string_lossy());
+            Ok(())
+        }
+        Command::Restore => {
+            let integration = integration(&opt, true)?;
+            integration.preflight()?;
+            let checkouts = integration.retrieve()?;
+            let fetched = checkouts.iter().filter(|checkout| checkout.fetched).count();
+            say!("{} project(s) retrieved", fetched);
+            Ok(())
+        }
+        Command::Serve { address, upstream } => {
+            let registry = integration(&opt, false)?.registry(&upstream)?;
+            for (name, version) in registry.packages() {
+                println!("{}@{}", name, version);
+            }
+            registry.serve(&address)
+        }
+        Command::Schema => {
+            print!("{}", json_schema());
+            Ok(())
+        }
+    }
 }
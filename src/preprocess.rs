@@ -0,0 +1,367 @@
+//! Resolution of the includes, variables and build profiles of a
+//! configuration, on the YAML document before it is deserialized.
+use crate::config::Format;
+use crate::validate::is_number_or_bool;
+use anyhow::{Context, Result};
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn key(name: &str) -> Value {
+    Value::String(String::from(name))
+}
+
+// Values of `over` replace the ones of `base`, except for mappings which are
+// merged and lists which are concatenated
+fn merge(base: Value, over: Value) -> Value {
+    match (base, over) {
+        (Value::Mapping(mut base), Value::Mapping(over)) => {
+            for (name, value) in over {
+                match base.get_mut(&name) {
+                    Some(previous) => *previous = merge(previous.clone(), value),
+                    None => {
+                        base.insert(name, value);
+                    }
+                }
+            }
+            Value::Mapping(base)
+        }
+        (Value::Sequence(mut base), Value::Sequence(over)) => {
+            base.extend(over);
+            Value::Sequence(base)
+        }
+        (_, over) => over,
+    }
+}
+
+fn read_document(path: &Path) -> Result<Value> {
+    let document = fs::read_to_string(path)
+        .with_context(|| format!("could not read file `{}`", path.to_string_lossy()))?;
+    Format::from_path(path)
+        .parse(&document)
+        .with_context(|| format!("invalid configuration `{}`", path.to_string_lossy()))
+}
+
+// Merge the files included by the document, relative to `base`, under it.
+// `chain` holds the files being included, to detect cycles.
+fn include(mut document: Value, base: &Path, chain: &mut Vec<PathBuf>) -> Result<Value> {
+    let includes = match document
+        .as_mapping_mut()
+        .and_then(|m| m.remove(&key("include")))
+    {
+        Some(Value::Sequence(includes)) => includes,
+        Some(Value::Null) | None => return Ok(document),
+        Some(_) => return Err(anyhow::anyhow!("`include` must be a list of files")),
+    };
+    let mut merged = Value::Mapping(Mapping::new());
+    for included in includes {
+        let included = included
+            .as_str()
+            .context("`include` must be a list of files")?;
+        let path = base.join(included);
+        let canonical = path
+            .canonicalize()
+            .with_context(|| format!("could not read file `{}`", path.to_string_lossy()))?;
+        if chain.contains(&canonical) {
+            return Err(anyhow::anyhow!(
+                "`{}` includes itself",
+                canonical.to_string_lossy()
+            ));
+        }
+        chain.push(canonical);
+        let folder = path.parent().unwrap_or(base).to_path_buf();
+        let document = include(read_document(&path)?, &folder, chain)
+            .with_context(|| format!("Trying to include {}", path.to_string_lossy()))?;
+        chain.pop();
+        merged = merge(merged, document);
+    }
+    Ok(merge(merged, document))
+}
+
+// Replace the `${name}` in `text` by the value `lookup` gives for the name,
+// left as is if it gives none, `$${` being a literal `${`
+fn interpolate(
+    text: &str,
+    lookup: &mut dyn FnMut(&str) -> Result<Option<String>>,
+) -> Result<String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            result += &rest[..start - 1];
+            result += "${";
+            rest = &rest[start + 2..];
+            continue;
+        }
+        result += &rest[..start];
+        let end = rest[start..]
+            .find('}')
+            .with_context(|| format!("unclosed `${{` in `{}`", text))?;
+        let name = &rest[start + 2..start + end];
+        match lookup(name).with_context(|| format!("Trying to interpolate `{}`", text))? {
+            Some(value) => result += &value,
+            None => result += &rest[start..start + end + 1],
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(result + rest)
+}
+
+// A variable, or else an environment variable
+fn lookup(name: &str, vars: &Mapping, env: &HashMap<String, String>) -> Result<String> {
+    match vars.get(&key(name)).and_then(Value::as_str) {
+        Some(value) => Ok(value.to_string()),
+        None => env
+            .get(name)
+            .cloned()
+            .with_context(|| format!("undefined variable `{}`", name)),
+    }
+}
+
+// Whether the strings at `path` are commands run by bash, which only get the
+// variables of `vars`: the other `${...}` are expanded by bash
+fn is_command(path: &[&str]) -> bool {
+    matches!(path, ["repositories", "build"] | ["profiles", _])
+}
+
+// Interpolate the strings of `value`, found at `path` in the configuration
+fn interpolate_all(
+    value: &mut Value,
+    vars: &Mapping,
+    env: &HashMap<String, String>,
+    path: &mut Vec<String>,
+) -> Result<()> {
+    match value {
+        Value::String(text) if text.contains("${") => {
+            let path = path.iter().map(String::as_str).collect::<Vec<_>>();
+            let interpolated = match is_command(&path) {
+                true => interpolate(text, &mut |name| {
+                    Ok(vars
+                        .get(&key(name))
+                        .and_then(Value::as_str)
+                        .map(String::from))
+                })?,
+                false => interpolate(text, &mut |name| lookup(name, vars, env).map(Some))?,
+            };
+            // A single variable can give its value to a number or boolean
+            // field, every other value stays a string
+            let single = text.starts_with("${") && text.find('}') == Some(text.len() - 1);
+            *value = match serde_yaml::from_str(&interpolated) {
+                Ok(scalar @ (Value::Number(_) | Value::Bool(_)))
+                    if single && is_number_or_bool(&path) =>
+                {
+                    scalar
+                }
+                _ => Value::String(interpolated),
+            };
+        }
+        Value::Sequence(items) => {
+            for item in items {
+                interpolate_all(item, vars, env, path)?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (name, item) in mapping.iter_mut() {
+                path.push(name.as_str().unwrap_or_default().to_string());
+                interpolate_all(item, vars, env, path)?;
+                path.pop();
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+// The value of a variable, resolving the variables it uses first. `chain`
+// holds the variables being resolved, to detect cycles.
+fn resolve_var(
+    name: &str,
+    vars: &Mapping,
+    env: &HashMap<String, String>,
+    resolved: &mut Mapping,
+    chain: &mut Vec<String>,
+) -> Result<String> {
+    if let Some(value) = resolved.get(&key(name)).and_then(Value::as_str) {
+        return Ok(value.to_string());
+    }
+    let text = match vars.get(&key(name)) {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Number(number)) => number.to_string(),
+        Some(Value::Bool(flag)) => flag.to_string(),
+        Some(_) => return Err(anyhow::anyhow!("variable `{}` must be a string", name)),
+        None => return lookup(name, resolved, env),
+    };
+    if chain.iter().any(|resolving| resolving == name) {
+        return Err(anyhow::anyhow!("variable `{}` refers to itself", name));
+    }
+    chain.push(name.to_string());
+    let value = interpolate(&text, &mut |name| {
+        resolve_var(name, vars, env, resolved, chain).map(Some)
+    })?;
+    chain.pop();
+    resolved.insert(key(name), Value::String(value.clone()));
+    Ok(value)
+}
+
+// The variables as strings, in any order of definition
+fn resolve_vars(vars: Option<&Value>, env: &HashMap<String, String>) -> Result<Mapping> {
+    let mut resolved = Mapping::new();
+    let vars = match vars {
+        Some(Value::Mapping(vars)) => vars,
+        Some(Value::Null) | None => return Ok(resolved),
+        Some(_) => return Err(anyhow::anyhow!("`vars` must be a mapping")),
+    };
+    for name in vars.iter().filter_map(|(name, _)| name.as_str()) {
+        resolve_var(name, vars, env, &mut resolved, &mut vec![])?;
+    }
+    // In the order of the configuration
+    Ok(vars
+        .iter()
+        .filter_map(|(name, _)| Some((name.clone(), resolved.get(name)?.clone())))
+        .collect())
+}
+
+// Prepend the commands of the profile of each entry to its own
+fn apply_profiles(document: &mut Mapping) -> Result<()> {
+    let profiles = document
+        .get(&key("profiles"))
+        .cloned()
+        .unwrap_or(Value::Null);
+    let entries = match document.get_mut(&key("repositories")) {
+        Some(Value::Sequence(entries)) => entries,
+        _ => return Ok(()),
+    };
+    for entry in entries.iter_mut().filter_map(Value::as_mapping_mut) {
+        let profile = match entry.get(&key("profile")) {
+            Some(Value::String(profile)) => profile.clone(),
+            _ => continue,
+        };
+        let mut build = match profiles.get(profile.as_str()) {
+            Some(Value::Sequence(commands)) => commands.clone(),
+            _ => return Err(anyhow::anyhow!("unknown build profile `{}`", profile)),
+        };
+        if let Some(Value::Sequence(commands)) = entry.get(&key("build")) {
+            build.extend(commands.iter().cloned());
+        }
+        entry.insert(key("build"), Value::Sequence(build));
+    }
+    Ok(())
+}
+
+/// Merge the included files, relative to `base`, substitute the variables,
+/// looking up the undefined ones in `env`, and expand the build profiles of a
+/// configuration document.
+pub(crate) fn resolve(
+    document: Value,
+    base: &Path,
+    env: &HashMap<String, String>,
+) -> Result<Value> {
+    let mut document = include(document, base, &mut vec![])?;
+    let mapping = match document.as_mapping_mut() {
+        Some(mapping) => mapping,
+        None => return Ok(document),
+    };
+    let vars = resolve_vars(mapping.get(&key("vars")), env)?;
+    for (name, value) in mapping.iter_mut() {
+        if let Some(name) = name.as_str().filter(|name| *name != "vars") {
+            interpolate_all(value, &vars, env, &mut vec![name.to_string()])?;
+        }
+    }
+    if let Some(resolved) = mapping.get_mut(&key("vars")) {
+        *resolved = Value::Mapping(vars);
+    }
+    apply_profiles(mapping)?;
+    Ok(document)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("common")).unwrap();
+        fs::write(
+            root.path().join("common").join("base.yaml"),
+            r#"
+vars:
+  git: http://mygit.com/${team}
+profiles:
+  web: [npm ci, npm run build]
+repositories:
+  - url: ${git}/project-A
+    branch: master
+    profile: web
+"#,
+        )
+        .unwrap();
+        let document = serde_yaml::from_str(
+            r#"
+include: [common/base.yaml]
+vars:
+  team: user
+  retries: 3
+  year: 2024
+  check: true
+retry:
+  clone:
+    attempts: ${retries}
+repositories:
+  - url: ${git}/project-B
+    branch: ${INTEG_TEST_BRANCH}
+    profile: web
+    build: ["echo $${team}", "${check}", "cd ${1:-.} && echo ${HOME}"]
+  - url: ${git}/project-C
+    branch: ${year}
+    lfs: ${check}
+    build: ["${retries}"]
+"#,
+        )
+        .unwrap();
+        // Only the variables of `vars` are substituted in the build commands
+        let env = vec![
+            (String::from("INTEG_TEST_BRANCH"), String::from("fix-issue")),
+            (String::from("HOME"), String::from("/home/me")),
+        ]
+        .into_iter()
+        .collect();
+        let document = resolve(document, root.path(), &env).unwrap();
+        let expected: Value = serde_yaml::from_str(
+            r#"
+vars:
+  git: http://mygit.com/user
+  team: user
+  retries: "3"
+  year: "2024"
+  check: "true"
+profiles:
+  web: [npm ci, npm run build]
+repositories:
+  - url: http://mygit.com/user/project-A
+    branch: master
+    profile: web
+    build: [npm ci, npm run build]
+  - url: http://mygit.com/user/project-B
+    branch: fix-issue
+    profile: web
+    build: [npm ci, npm run build, "echo ${team}", "true", "cd ${1:-.} && echo ${HOME}"]
+  - url: http://mygit.com/user/project-C
+    branch: "2024"
+    lfs: true
+    build: ["3"]
+retry:
+  clone:
+    attempts: 3
+"#,
+        )
+        .unwrap();
+        assert_eq!(document, expected);
+
+        let document = serde_yaml::from_str("include: [loop.yaml]").unwrap();
+        fs::write(root.path().join("loop.yaml"), "include: [loop.yaml]").unwrap();
+        let error = resolve(document, root.path(), &env).unwrap_err();
+        assert!(format!("{:#}", error).contains("includes itself"));
+    }
+}
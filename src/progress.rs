@@ -0,0 +1,63 @@
+//! The `integ.progress` file recording the package generated for each
+//! project, so that a new run only rebuilds what changed.
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+pub(crate) fn dump_package_paths(
+    progress_filename: &str,
+    package_paths: &HashMap<String, String>,
+) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(progress_filename)?;
+    for (project_name, package_path) in package_paths {
+        writeln!(file, "{} {}", project_name, package_path)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn load_package_paths(progress_filename: &str) -> Result<HashMap<String, String>> {
+    let result = std::fs::read_to_string(progress_filename)
+        .with_context(|| format!("reading {}", progress_filename))?
+        .split('\n')
+        .map(|line| line.split(' ').collect::<Vec<&str>>())
+        .filter(|line| line.len() == 2)
+        // ignore entry for which package file do not exists
+        .filter(|line| PathBuf::from(line[1]).exists())
+        .map(|line| (String::from(line[0]), String::from(line[1])))
+        .collect();
+
+    Ok(result)
+}
+
+/// Record the error each project last failed to build with, reduced to its
+/// first line, in `integ.failures`.
+pub(crate) fn dump_failures(
+    failures_filename: &str,
+    failures: &HashMap<String, String>,
+) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(failures_filename)?;
+    for (project_name, error) in failures {
+        writeln!(file, "{} {}", project_name, error)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn load_failures(failures_filename: &str) -> Result<HashMap<String, String>> {
+    let result = std::fs::read_to_string(failures_filename)
+        .with_context(|| format!("reading {}", failures_filename))?
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(project_name, error)| (String::from(project_name), String::from(error)))
+        .collect();
+
+    Ok(result)
+}
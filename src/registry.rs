@@ -0,0 +1,284 @@
+//! A minimal npm registry serving the packages built by integ, so that they
+//! can be installed with a plain `.npmrc` (`registry=http://localhost:4873/`).
+//!
+//! Only the packument and tarball endpoints of the integrated packages are
+//! served. Every other request is answered with a redirect to an upstream
+//! registry rather than proxied: the client has to reach the upstream itself,
+//! and the requests it does not follow redirects for fail.
+use crate::event::{self, say};
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Value};
+use sha1::Sha1;
+use sha2::{Digest, Sha512};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use tiny_http::{Header, Response, Server};
+
+struct Package {
+    version: String,
+    /// The package.json the packument is built from
+    manifest: Value,
+    tarball: PathBuf,
+    /// The hex SHA-1 of the tarball, checked by the older npm clients
+    shasum: String,
+    /// The Subresource Integrity of the tarball, with SHA-512
+    integrity: String,
+}
+
+/// Where a request is answered from.
+#[derive(Debug, PartialEq)]
+enum Route {
+    Packument(Value),
+    Tarball(PathBuf),
+    Upstream(String),
+}
+
+pub struct Registry {
+    upstream: String,
+    /// The dist-tag the packages are served under, besides `latest`
+    tag: String,
+    packages: HashMap<String, Package>,
+}
+
+impl Registry {
+    /// A registry redirecting the requests it cannot answer to `upstream`,
+    /// serving the packages under the `tag` dist-tag.
+    pub fn new(upstream: impl Into<String>, tag: impl Into<String>) -> Self {
+        Registry {
+            upstream: upstream.into(),
+            tag: tag.into(),
+            packages: HashMap::new(),
+        }
+    }
+
+    /// Serve `tarball`, whose package.json is `manifest`. The tarball is read
+    /// to compute the digests npm checks it against.
+    pub fn add(&mut self, manifest: Value, tarball: impl Into<PathBuf>) -> Result<()> {
+        let name = manifest["name"]
+            .as_str()
+            .context("package.json without a name")?
+            .to_string();
+        let version = manifest["version"]
+            .as_str()
+            .with_context(|| format!("package.json of {} without a version", name))?
+            .to_string();
+        let tarball = tarball.into();
+        let content =
+            fs::read(&tarball).with_context(|| format!("reading {}", tarball.to_string_lossy()))?;
+        self.packages.insert(
+            name,
+            Package {
+                version,
+                manifest,
+                tarball,
+                shasum: format!("{:x}", Sha1::digest(&content)),
+                integrity: format!("sha512-{}", STANDARD.encode(Sha512::digest(&content))),
+            },
+        );
+        Ok(())
+    }
+
+    /// The name and version of the served packages.
+    pub fn packages(&self) -> Vec<(&str, &str)> {
+        let mut packages = self
+            .packages
+            .iter()
+            .map(|(name, package)| (name.as_str(), package.version.as_str()))
+            .collect::<Vec<_>>();
+        packages.sort();
+        packages
+    }
+
+    fn packument(&self, name: &str, package: &Package, base_url: &str) -> Value {
+        let file = package.tarball.file_name().unwrap().to_string_lossy();
+        let mut manifest = package.manifest.clone();
+        manifest["_id"] = json!(format!("{}@{}", name, package.version));
+        manifest["dist"] = json!({
+            "tarball": format!("{}/{}/-/{}", base_url, name, file),
+            "shasum": package.shasum,
+            "integrity": package.integrity,
+        });
+        json!({
+            "name": name,
+            "dist-tags": { "latest": package.version, self.tag.clone(): package.version },
+            "versions": { package.version.clone(): manifest },
+        })
+    }
+
+    fn route(&self, url: &str, base_url: &str) -> Route {
+        let path = url.split('?').next().unwrap_or("");
+        // Scoped names are requested as /@scope%2fname
+        let path = path.replace("%2f", "/").replace("%2F", "/");
+        let path = path.trim_start_matches('/');
+        match path.split_once("/-/") {
+            Some((name, file)) => match self.packages.get(name) {
+                Some(package) if package.tarball.file_name().unwrap().to_string_lossy() == file => {
+                    return Route::Tarball(package.tarball.clone())
+                }
+                _ => (),
+            },
+            None => {
+                if let Some(package) = self.packages.get(path) {
+                    return Route::Packument(self.packument(path, package, base_url));
+                }
+            }
+        }
+        Route::Upstream(format!("{}{}", self.upstream.trim_end_matches('/'), url))
+    }
+
+    /// Answer requests on `address` (e.g. `127.0.0.1:4873`) until killed.
+    pub fn serve(&self, address: &str) -> Result<()> {
+        let server = Server::http(address)
+            .map_err(|error| anyhow::anyhow!("could not listen on {}: {}", address, error))?;
+        say!("Serving on http://{}/", address);
+        for request in server.incoming_requests() {
+            let host = request
+                .headers()
+                .iter()
+                .find(|header| header.field.equiv("Host"))
+                .map(|header| header.value.to_string())
+                .unwrap_or_else(|| address.to_string());
+            let route = self.route(request.url(), &format!("http://{}", host));
+            let target = match &route {
+                Route::Packument(_) => String::from("packument"),
+                Route::Tarball(tarball) => tarball.to_string_lossy().to_string(),
+                Route::Upstream(url) => url.clone(),
+            };
+            say!("{} {} -> {}", request.method(), request.url(), target);
+            let result = match route {
+                Route::Packument(packument) => request.respond(
+                    Response::from_string(packument.to_string())
+                        .with_header(header("Content-Type", "application/json")),
+                ),
+                Route::Tarball(tarball) => match File::open(&tarball) {
+                    Ok(file) => request.respond(
+                        Response::from_file(file)
+                            .with_header(header("Content-Type", "application/octet-stream")),
+                    ),
+                    Err(_) => request.respond(Response::empty(404)),
+                },
+                // 307 keeps the method and the body of the request
+                Route::Upstream(url) => {
+                    request.respond(Response::empty(307).with_header(header("Location", &url)))
+                }
+            };
+            if let Err(error) = result {
+                event::warn(format_args!("could not respond: {}", error));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::process::Command;
+
+    #[test]
+    fn test_route() {
+        let root = tempfile::tempdir().unwrap();
+        let tarball = root.path().join("x-a-1.0.0-integ.1.tgz");
+        fs::write(&tarball, "").unwrap();
+        let mut registry = Registry::new("https://registry.npmjs.org/", "next");
+        registry
+            .add(
+                json!({ "name": "@x/a", "version": "1.0.0-integ.1", "main": "index.js" }),
+                &tarball,
+            )
+            .unwrap();
+        assert_eq!(registry.packages(), vec![("@x/a", "1.0.0-integ.1")]);
+        let base_url = "http://localhost:4873";
+
+        match registry.route("/@x%2fa", base_url) {
+            Route::Packument(packument) => {
+                assert_eq!(packument["dist-tags"]["latest"], "1.0.0-integ.1");
+                assert_eq!(packument["dist-tags"]["next"], "1.0.0-integ.1");
+                let version = &packument["versions"]["1.0.0-integ.1"];
+                assert_eq!(version["main"], "index.js");
+                assert_eq!(
+                    version["dist"]["tarball"],
+                    "http://localhost:4873/@x/a/-/x-a-1.0.0-integ.1.tgz"
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            registry.route("/@x/a/-/x-a-1.0.0-integ.1.tgz", base_url),
+            Route::Tarball(tarball.clone())
+        );
+        assert_eq!(
+            registry.route("/@x/a/-/x-a-0.9.0.tgz", base_url),
+            Route::Upstream(String::from(
+                "https://registry.npmjs.org/@x/a/-/x-a-0.9.0.tgz"
+            ))
+        );
+        assert_eq!(
+            registry.route("/lodash?write=true", base_url),
+            Route::Upstream(String::from("https://registry.npmjs.org/lodash?write=true"))
+        );
+    }
+
+    // The body of the response to a GET of `path`
+    fn get(address: &str, path: &str) -> Vec<u8> {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, address).unwrap();
+        let mut response = vec![];
+        stream.read_to_end(&mut response).unwrap();
+        let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        assert!(response.starts_with(b"HTTP/1.0 200"), "{:?}", response);
+        response.split_off(end + 4)
+    }
+
+    #[test]
+    fn test_serve() {
+        let root = tempfile::tempdir().unwrap();
+        let folder = root.path().to_string_lossy().to_string();
+        // Packed with `npm pack` like the projects integ builds
+        let package_json = json!({ "name": "@x/a", "version": "1.0.0" });
+        fs::write(root.path().join("package.json"), package_json.to_string()).unwrap();
+        fs::write(root.path().join("index.js"), "module.exports = 42;\n").unwrap();
+        // Along with the digests npm computes itself
+        let packed = command::run(
+            Command::new("npm")
+                .current_dir(&folder)
+                .args(["pack", "--json"]),
+            None,
+        )
+        .unwrap();
+        let packed: Value = serde_json::from_str(&packed.stdout).unwrap();
+        let tarball = root.path().join(packed[0]["filename"].as_str().unwrap());
+
+        let mut registry = Registry::new("https://registry.npmjs.org/", "integ");
+        registry.add(package_json, &tarball).unwrap();
+        // A free port, released for the registry to listen on
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let listening = address.clone();
+        std::thread::spawn(move || registry.serve(&listening));
+        while TcpStream::connect(&address).is_err() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let packument: Value = serde_json::from_slice(&get(&address, "/@x%2fa")).unwrap();
+        let dist = &packument["versions"]["1.0.0"]["dist"];
+        assert_eq!(dist["shasum"], packed[0]["shasum"]);
+        assert_eq!(dist["integrity"], packed[0]["integrity"]);
+        let url = dist["tarball"].as_str().unwrap();
+        let path = url.trim_start_matches(&format!("http://{}", address));
+        assert_eq!(get(&address, path), fs::read(&tarball).unwrap());
+    }
+}
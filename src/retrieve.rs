@@ -0,0 +1,432 @@
+//! Cloning and copying of the projects into the output folder.
+use crate::command;
+use crate::config::{seconds, Entry, Folder, Repo, Retries, RetryPolicy, Submodules, Timeouts};
+use crate::event::say;
+use crate::event::{self, Event, Step};
+use crate::retry::with_retry;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+/// The name of the folder a repository is cloned into.
+pub(crate) fn repo_folder(url: &str) -> &str {
+    url.split('/')
+        .next_back()
+        .unwrap()
+        .split('.')
+        .next()
+        .unwrap()
+}
+
+/// The name of the folder a local project is copied into.
+pub(crate) fn local_folder(path: &str) -> &str {
+    path.split('/').next_back().unwrap()
+}
+
+/// The folder each entry is retrieved into, inside `output_path`.
+pub fn folder_names(output_path: &Path, entries: &[Entry]) -> Vec<String> {
+    entries
+        .iter()
+        .map(Entry::folder_name)
+        .map(|f| output_path.join(f).to_string_lossy().to_string())
+        .collect::<_>()
+}
+
+fn mirror_name(url: &str) -> String {
+    // Flatten the url into a single folder name, e.g.
+    // https://mygit.com/user/project-A -> https___mygit.com_user_project-A
+    url.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn update_mirror(url: &str, cache: &Path, timeout: Option<Duration>) -> Result<PathBuf> {
+    fs::create_dir_all(cache)
+        .with_context(|| format!("Trying to create cache folder {}", cache.to_string_lossy()))?;
+    let mirror = cache.join(mirror_name(url));
+    if mirror.exists() {
+        say!("updating mirror of {}", url);
+        command::run(
+            Command::new("git")
+                .arg("--git-dir")
+                .arg(&mirror)
+                .arg("fetch")
+                .arg("--prune")
+                .arg("origin"),
+            timeout,
+        )
+        .with_context(|| format!("Trying to update the mirror of {}", url))?;
+    } else {
+        say!("mirroring {}", url);
+        command::run(
+            Command::new("git")
+                .arg("clone")
+                .arg("--mirror")
+                .arg(url)
+                .arg(&mirror),
+            timeout,
+        )
+        .with_context(|| format!("Trying to mirror {}", url))?;
+    }
+    Ok(mirror)
+}
+
+fn git_in(folder: &str, args: &[&str], timeout: Option<Duration>) -> Result<()> {
+    command::run(Command::new("git").current_dir(folder).args(args), timeout)
+        .with_context(|| format!("Trying to run git {} in {}", args.join(" "), folder))?;
+    Ok(())
+}
+
+fn clone_repository(
+    repository: &Repo,
+    folder: &str,
+    cache: Option<&Path>,
+    retry: &RetryPolicy,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    with_retry(retry, &format!("cloning {}", repository.url), || {
+        fetch_repository(repository, folder, cache, timeout).inspect_err(|_| {
+            // Start the next attempt from scratch
+            fs::remove_dir_all(folder).ok();
+        })
+    })
+}
+
+fn fetch_repository(
+    repository: &Repo,
+    folder: &str,
+    cache: Option<&Path>,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    say!("cloning {}", repository.url);
+    // LFS objects are pulled explicitly once the clone is done so that a
+    // missing object is reported as such rather than as a failed checkout.
+    let skip_smudge = if repository.lfs { "1" } else { "0" };
+    match cache {
+        // Clone locally from the mirror (objects are hardlinked) and point
+        // origin back to the real repository.
+        Some(cache) => {
+            let mirror = update_mirror(&repository.url, cache, timeout)?;
+            command::run(
+                Command::new("git")
+                    .env("GIT_LFS_SKIP_SMUDGE", skip_smudge)
+                    .arg("clone")
+                    .arg("--branch")
+                    .arg(&repository.branch)
+                    .arg(&mirror)
+                    .arg(folder),
+                timeout,
+            )
+            .with_context(|| format!("Trying to clone {}", repository.url))?;
+            git_in(
+                folder,
+                &["remote", "set-url", "origin", &repository.url],
+                timeout,
+            )?;
+        }
+        None => {
+            command::run(
+                Command::new("git")
+                    .env("GIT_LFS_SKIP_SMUDGE", skip_smudge)
+                    .arg("clone")
+                    .arg("--branch")
+                    .arg(&repository.branch)
+                    .arg("--depth")
+                    .arg("1")
+                    .arg(&repository.url)
+                    .arg(folder),
+                timeout,
+            )
+            .with_context(|| format!("Trying to clone {}", repository.url))?;
+        }
+    }
+
+    match repository.submodules {
+        Submodules::Disabled => (),
+        Submodules::Enabled => {
+            say!("fetching submodules of {}", repository.url);
+            git_in(
+                folder,
+                &["submodule", "update", "--init", "--depth", "1"],
+                timeout,
+            )?;
+        }
+        Submodules::Recursive => {
+            say!("fetching submodules of {} recursively", repository.url);
+            git_in(
+                folder,
+                &[
+                    "submodule",
+                    "update",
+                    "--init",
+                    "--recursive",
+                    "--depth",
+                    "1",
+                ],
+                timeout,
+            )?;
+        }
+    }
+
+    if repository.lfs {
+        say!("pulling LFS objects of {}", repository.url);
+        git_in(folder, &["lfs", "install", "--local"], timeout)?;
+        git_in(folder, &["lfs", "pull"], timeout)?;
+        match repository.submodules {
+            Submodules::Disabled => (),
+            Submodules::Enabled => {
+                git_in(folder, &["submodule", "foreach", "git lfs pull"], timeout)?
+            }
+            Submodules::Recursive => git_in(
+                folder,
+                &["submodule", "foreach", "--recursive", "git lfs pull"],
+                timeout,
+            )?,
+        }
+    }
+    Ok(())
+}
+
+fn copy_folder(repository: &Folder, folder: &str) -> Result<()> {
+    if command::is_available("rsync") {
+        say!("syncing {} to {}", repository.path, folder);
+        command::run(
+            Command::new("rsync")
+                .arg("-av")
+                .arg(format!("{}/", &repository.path))
+                .arg(folder)
+                .arg("--exclude=node_modules"),
+            None,
+        )
+    } else {
+        say!("copying {} to {}", repository.path, folder);
+        command::run(
+            Command::new("cp")
+                .arg("-r")
+                .arg(&repository.path)
+                .arg(folder),
+            None,
+        )
+    }
+    .with_context(|| format!("Trying to sync/copy {}", repository.path))?;
+    Ok(())
+}
+
+/// Where a project has been retrieved.
+#[derive(Debug)]
+pub struct Checkout {
+    pub folder: String,
+    /// False if the folder already existed and was left untouched
+    pub fetched: bool,
+    /// How long the clone or copy took
+    pub duration: Duration,
+}
+
+// The branch checked out in a clone, None if detached or not a clone
+fn checked_out_branch(folder: &str) -> Option<String> {
+    let output = command::run(
+        Command::new("git")
+            .current_dir(folder)
+            .args(["rev-parse", "--abbrev-ref", "HEAD"]),
+        None,
+    )
+    .ok()?;
+    Some(output.stdout.trim().to_string()).filter(|branch| branch != "HEAD")
+}
+
+pub(crate) fn retrieve_repositories(
+    repositories: &[Entry],
+    folders: &[String],
+    cache: Option<&Path>,
+    retry: &Retries,
+    timeouts: &Timeouts,
+) -> Result<Vec<Checkout>> {
+    let mut checkouts = vec![];
+    assert_eq!(repositories.len(), folders.len());
+    for (index, repository) in repositories.iter().enumerate() {
+        if PathBuf::from(&folders[index]).exists() {
+            // The branch asked for is not silently ignored
+            if let Entry::Repo(repo) = repository {
+                if let Some(branch) = checked_out_branch(&folders[index]) {
+                    if branch != repo.branch {
+                        return Err(anyhow::anyhow!(
+                            "{} is on branch `{}` instead of `{}`, clean it with \
+                             `integ clean --level projects {}` to clone it again",
+                            folders[index],
+                            branch,
+                            repo.branch,
+                            repository.folder_name()
+                        ));
+                    }
+                }
+            }
+            say!("{} already exists, skipping", folders[index]);
+            event::emit(Event::ProjectRetrieved {
+                project: repository.folder_name().to_string(),
+                folder: folders[index].clone(),
+                fetched: false,
+            });
+            checkouts.push(Checkout {
+                folder: folders[index].clone(),
+                fetched: false,
+                duration: Duration::default(),
+            });
+            continue;
+        }
+        let name = repository.folder_name();
+        let (result, duration) = match repository {
+            Entry::Repo(repo) => event::step(name, Step::Clone, None, || {
+                clone_repository(
+                    repo,
+                    &folders[index],
+                    cache,
+                    &retry.clone,
+                    seconds(timeouts.clone),
+                )
+            }),
+            Entry::Folder(folder) => event::step(name, Step::Copy, None, || {
+                copy_folder(folder, &folders[index])
+            }),
+        };
+        result?;
+        event::emit(Event::ProjectRetrieved {
+            project: name.to_string(),
+            folder: folders[index].clone(),
+            fetched: true,
+        });
+        checkouts.push(Checkout {
+            folder: folders[index].clone(),
+            fetched: true,
+            duration,
+        });
+    }
+
+    Ok(checkouts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::default_build_retry;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let output = Command::new("git")
+            .current_dir(dir)
+            .args(["-c", "user.name=integ", "-c", "user.email=integ@localhost"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?}: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    // Create a bare repository with a single commit on master and return its url
+    fn bare_repository(root: &Path, name: &str) -> String {
+        let bare = root.join(format!("{}.git", name));
+        let work = root.join(format!("{}-work", name));
+        fs::create_dir_all(&work).unwrap();
+        git(root, &["init", "--bare", bare.to_str().unwrap()]);
+        git(&work, &["init"]);
+        fs::write(work.join("package.json"), r#"{ "name": "a" }"#).unwrap();
+        git(&work, &["add", "."]);
+        git(&work, &["commit", "-m", "first"]);
+        git(
+            &work,
+            &["push", bare.to_str().unwrap(), "HEAD:refs/heads/master"],
+        );
+        format!("file://{}", bare.to_string_lossy())
+    }
+
+    #[test]
+    fn test_mirror_name() {
+        assert_eq!(
+            mirror_name("https://mygit.com/user/project-A"),
+            "https___mygit.com_user_project-A"
+        );
+        assert_eq!(
+            mirror_name("git@mygit.com:user/project-A.git"),
+            "git_mygit.com_user_project-A.git"
+        );
+    }
+
+    #[test]
+    fn test_clone_through_cache() {
+        let root = tempfile::tempdir().unwrap();
+        let url = bare_repository(root.path(), "a");
+        let cache = root.path().join("cache");
+        let repo = Repo {
+            url: url.clone(),
+            branch: String::from("master"),
+            build: vec![],
+            submodules: Submodules::Disabled,
+            lfs: false,
+            profile: None,
+            ecosystem: Default::default(),
+        };
+        let retry = default_build_retry();
+
+        let first = root.path().join("first");
+        clone_repository(&repo, first.to_str().unwrap(), Some(&cache), &retry, None).unwrap();
+        assert!(first.join("package.json").exists());
+        assert!(cache.join(mirror_name(&url)).join("HEAD").exists());
+        let origin = Command::new("git")
+            .current_dir(&first)
+            .args(["remote", "get-url", "origin"])
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8(origin.stdout).unwrap().trim(), url);
+
+        // A new commit upstream must be fetched into the mirror on the next clone
+        let work = root.path().join("a-work");
+        fs::write(work.join("index.js"), "").unwrap();
+        git(&work, &["add", "."]);
+        git(&work, &["commit", "-m", "second"]);
+        git(&work, &["push", &url, "HEAD:refs/heads/master"]);
+        let second = root.path().join("second");
+        clone_repository(&repo, second.to_str().unwrap(), Some(&cache), &retry, None).unwrap();
+        assert!(second.join("index.js").exists());
+    }
+
+    #[test]
+    fn test_existing_checkout() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = Repo {
+            url: bare_repository(root.path(), "a"),
+            branch: String::from("master"),
+            build: vec![],
+            submodules: Submodules::Disabled,
+            lfs: false,
+            profile: None,
+            ecosystem: Default::default(),
+        };
+        let folder = root.path().join("out").join("a");
+        let folders = vec![folder.to_string_lossy().to_string()];
+        clone_repository(&repo, &folders[0], None, &default_build_retry(), None).unwrap();
+        let (retry, timeouts) = (Retries::default(), Timeouts::default());
+
+        let mut entries = vec![Entry::Repo(repo)];
+        let checkouts = retrieve_repositories(&entries, &folders, None, &retry, &timeouts).unwrap();
+        assert!(!checkouts[0].fetched);
+
+        if let Entry::Repo(repo) = &mut entries[0] {
+            repo.branch = String::from("fix-issue");
+        }
+        let error = retrieve_repositories(&entries, &folders, None, &retry, &timeouts)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("is on branch `master` instead of `fix-issue`"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! Retrying of the steps which can fail transiently (network access...).
+use crate::config::RetryPolicy;
+use crate::event;
+use anyhow::Result;
+use std::thread;
+use std::time::Duration;
+
+/// Call `f` until it succeeds or `policy.attempts` is reached, waiting
+/// between attempts as per the policy backoff.
+pub(crate) fn with_retry<T>(
+    policy: &RetryPolicy,
+    step: &str,
+    mut f: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    let attempts = policy.attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(error) if attempt < attempts => {
+                let delay = Duration::from_millis(
+                    policy
+                        .backoff_ms
+                        .saturating_mul(2u64.saturating_pow(attempt - 1)),
+                );
+                event::retry(step, attempt, attempts, delay, &error);
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::default_build_retry;
+
+    #[test]
+    fn test_retry() {
+        let policy = RetryPolicy {
+            attempts: 3,
+            backoff_ms: 0,
+        };
+        let mut calls = 0;
+        let result = with_retry(&policy, "flaky", || {
+            calls += 1;
+            if calls < 3 {
+                Err(anyhow::anyhow!("ECONNRESET"))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let result: Result<()> = with_retry(&policy, "broken", || {
+            calls += 1;
+            Err(anyhow::anyhow!("ECONNRESET"))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let result: Result<()> = with_retry(&default_build_retry(), "build", || {
+            calls += 1;
+            Err(anyhow::anyhow!("test failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,183 @@
+//! The state of the projects of an output folder, as left by the previous
+//! runs.
+use crate::command;
+use crate::config::Entry;
+use std::fmt;
+use std::process::Command;
+
+/// Where a project comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Repo {
+        url: String,
+        branch: String,
+        /// The abbreviated commit checked out, once cloned
+        sha: Option<String>,
+    },
+    Folder {
+        path: String,
+    },
+}
+
+/// How a checkout differs from the branch it was cloned from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Changes {
+    /// Tracked files modified, added or deleted in the working tree,
+    /// including the manifests patched by the build
+    pub files: usize,
+    /// Local commits missing from the remote branch
+    pub ahead: usize,
+    /// Commits of the remote branch missing from the checkout, as of the
+    /// last fetch
+    pub behind: usize,
+}
+
+impl Changes {
+    pub fn is_clean(&self) -> bool {
+        *self == Changes::default()
+    }
+}
+
+/// The state of a project in the output folder.
+#[derive(Debug)]
+pub struct ProjectStatus {
+    /// The package name, or the folder name if it cannot be read
+    pub name: String,
+    pub folder: String,
+    pub source: Source,
+    pub retrieved: bool,
+    /// None if the project was not cloned from a repository
+    pub changes: Option<Changes>,
+    /// The package of the last successful build
+    pub artifact: Option<String>,
+    /// Whether the next build would rebuild the project despite its
+    /// artifact, None if it cannot be told (e.g. a project is missing)
+    pub stale: Option<bool>,
+    /// The first line of the error of the last failed build, if the project
+    /// has not been built successfully since
+    pub last_failure: Option<String>,
+}
+
+// The trimmed output of a git command run in the folder, if it succeeds
+fn git(folder: &str, args: &[&str]) -> Option<String> {
+    let output = command::run(Command::new("git").current_dir(folder).args(args), None).ok()?;
+    Some(output.stdout.trim().to_string())
+}
+
+pub(crate) fn source(entry: &Entry, folder: &str, retrieved: bool) -> Source {
+    match entry {
+        Entry::Repo(repo) => Source::Repo {
+            url: repo.url.clone(),
+            branch: repo.branch.clone(),
+            sha: match retrieved {
+                true => git(folder, &["rev-parse", "--short", "HEAD"]),
+                false => None,
+            },
+        },
+        Entry::Folder(local) => Source::Folder {
+            path: local.path.clone(),
+        },
+    }
+}
+
+pub(crate) fn changes(folder: &str) -> Option<Changes> {
+    let files = git(folder, &["status", "--porcelain", "--untracked-files=no"])?
+        .lines()
+        .count();
+    // No upstream branch (e.g. detached) is reported as in sync
+    let (ahead, behind) = git(
+        folder,
+        &["rev-list", "--left-right", "--count", "HEAD...@{upstream}"],
+    )
+    .and_then(|counts| {
+        let (ahead, behind) = counts.split_once(char::is_whitespace)?;
+        Some((ahead.trim().parse().ok()?, behind.trim().parse().ok()?))
+    })
+    .unwrap_or((0, 0));
+    Some(Changes {
+        files,
+        ahead,
+        behind,
+    })
+}
+
+impl fmt::Display for ProjectStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.name)?;
+        match &self.source {
+            Source::Repo { url, branch, sha } => {
+                write!(f, "  source:   {} ({}", url, branch)?;
+                match sha {
+                    Some(sha) => writeln!(f, " at {})", sha)?,
+                    None => writeln!(f, ")")?,
+                }
+            }
+            Source::Folder { path } => writeln!(f, "  source:   {}", path)?,
+        }
+        if !self.retrieved {
+            return writeln!(f, "  checkout: not retrieved");
+        }
+        match &self.changes {
+            Some(changes) if changes.is_clean() => writeln!(f, "  checkout: clean")?,
+            Some(changes) => {
+                let mut details = vec![];
+                if changes.files > 0 {
+                    details.push(format!("{} file(s) changed", changes.files));
+                }
+                if changes.ahead > 0 {
+                    details.push(format!("{} commit(s) ahead", changes.ahead));
+                }
+                if changes.behind > 0 {
+                    details.push(format!("{} commit(s) behind", changes.behind));
+                }
+                writeln!(f, "  checkout: {}", details.join(", "))?
+            }
+            None => writeln!(f, "  checkout: copied to {}", self.folder)?,
+        }
+        match (&self.artifact, self.stale) {
+            (None, _) => writeln!(f, "  artifact: not built")?,
+            (Some(artifact), Some(true)) => writeln!(f, "  artifact: {} (stale)", artifact)?,
+            (Some(artifact), Some(false)) => writeln!(f, "  artifact: {} (up to date)", artifact)?,
+            (Some(artifact), None) => writeln!(f, "  artifact: {}", artifact)?,
+        }
+        if let Some(failure) = &self.last_failure {
+            writeln!(f, "  failure:  {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let status = ProjectStatus {
+            name: String::from("@x/b"),
+            folder: String::from("/tmp/out/project-B"),
+            source: Source::Repo {
+                url: String::from("http://mygit.com/user/project-B"),
+                branch: String::from("fix-issue"),
+                sha: Some(String::from("1a2b3c4")),
+            },
+            retrieved: true,
+            changes: Some(Changes {
+                files: 2,
+                ahead: 0,
+                behind: 1,
+            }),
+            artifact: Some(String::from("/tmp/out/project-B/x-b-1.0.0.tgz")),
+            stale: Some(true),
+            last_failure: Some(String::from("Trying to build with npm test")),
+        };
+        assert_eq!(
+            status.to_string(),
+            "@x/b\n  \
+             source:   http://mygit.com/user/project-B (fix-issue at 1a2b3c4)\n  \
+             checkout: 2 file(s) changed, 1 commit(s) behind\n  \
+             artifact: /tmp/out/project-B/x-b-1.0.0.tgz (stale)\n  \
+             failure:  Trying to build with npm test\n"
+        );
+    }
+}
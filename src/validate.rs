@@ -0,0 +1,1027 @@
+//! Validation of a configuration file before it is deserialized.
+//!
+//! The deserializer stops at the first error and cannot say much about the
+//! untagged [`Entry`](crate::config::Entry), so the YAML is walked here
+//! instead, reporting every problem with its position in the file.
+use crate::config::Format;
+use crate::retrieve::{local_folder, repo_folder};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use toml_edit::{ImDocument, Item};
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle};
+
+/// Errors prevent the integration from running, warnings do not.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a configuration, at a 1-based line and column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub line: usize,
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.line, self.column, severity, self.message
+        )
+    }
+}
+
+// The expected content of a value
+enum Shape {
+    Bool,
+    Integer,
+    Text,
+    OneOf(&'static [&'static str]),
+    // true, false or recursive
+    Submodules,
+    List(&'static Shape),
+    // A mapping of names to values of a shape
+    Map(&'static Shape),
+    Fields(&'static [Field]),
+    // The entries of `repositories`, either repositories or folders
+    Entries,
+}
+
+struct Field {
+    name: &'static str,
+    shape: Shape,
+    required: bool,
+}
+
+const fn optional(name: &'static str, shape: Shape) -> Field {
+    Field {
+        name,
+        shape,
+        required: false,
+    }
+}
+
+const fn required(name: &'static str, shape: Shape) -> Field {
+    Field {
+        name,
+        shape,
+        required: true,
+    }
+}
+
+const ECOSYSTEMS: &[&str] = &["npm", "cargo", "python"];
+const DEPENDENCY_KINDS: &[&str] = &["normal", "dev", "peer", "optional", "build"];
+
+const REPO: &[Field] = &[
+    required("url", Shape::Text),
+    required("branch", Shape::Text),
+    optional("build", Shape::List(&Shape::Text)),
+    optional("profile", Shape::Text),
+    optional("submodules", Shape::Submodules),
+    optional("lfs", Shape::Bool),
+    optional("ecosystem", Shape::OneOf(ECOSYSTEMS)),
+];
+
+const FOLDER: &[Field] = &[
+    required("path", Shape::Text),
+    optional("build", Shape::List(&Shape::Text)),
+    optional("profile", Shape::Text),
+    optional("ecosystem", Shape::OneOf(ECOSYSTEMS)),
+];
+
+// Any entry, when it is unclear which one is meant
+const ENTRY: &[Field] = &[
+    optional("url", Shape::Text),
+    optional("path", Shape::Text),
+    optional("branch", Shape::Text),
+    optional("build", Shape::List(&Shape::Text)),
+    optional("profile", Shape::Text),
+    optional("submodules", Shape::Submodules),
+    optional("lfs", Shape::Bool),
+    optional("ecosystem", Shape::OneOf(ECOSYSTEMS)),
+];
+
+const RETRY_POLICY: &[Field] = &[
+    optional("attempts", Shape::Integer),
+    optional("backoff_ms", Shape::Integer),
+];
+
+const RETRIES: &[Field] = &[
+    optional("clone", Shape::Fields(RETRY_POLICY)),
+    optional("install", Shape::Fields(RETRY_POLICY)),
+    optional("build", Shape::Fields(RETRY_POLICY)),
+    optional("publish", Shape::Fields(RETRY_POLICY)),
+];
+
+const TIMEOUTS: &[Field] = &[
+    optional("clone", Shape::Integer),
+    optional("install", Shape::Integer),
+    optional("build", Shape::Integer),
+    optional("pack", Shape::Integer),
+    optional("publish", Shape::Integer),
+];
+
+const PUBLISH: &[Field] = &[
+    required("registry", Shape::Text),
+    optional("tag", Shape::Text),
+    optional("link", Shape::OneOf(&["file", "registry"])),
+];
+
+const CONFIG: &[Field] = &[
+    optional("include", Shape::List(&Shape::Text)),
+    optional("vars", Shape::Map(&Shape::Text)),
+    optional("profiles", Shape::Map(&Shape::List(&Shape::Text))),
+    required("repositories", Shape::Entries),
+    optional("workers", Shape::Integer),
+    optional("cache", Shape::Text),
+    optional("retry", Shape::Fields(RETRIES)),
+    optional("timeouts", Shape::Fields(TIMEOUTS)),
+    optional("edges", Shape::List(&Shape::OneOf(DEPENDENCY_KINDS))),
+    optional("version_check", Shape::OneOf(&["ignore", "warn", "error"])),
+    optional("stamp", Shape::Bool),
+    optional("publish", Shape::Fields(PUBLISH)),
+    optional("lockfile", Shape::OneOf(&["discard", "preserve"])),
+];
+
+/// Whether the value at `path`, the keys leading to it from the root of the
+/// configuration, is a number or a boolean rather than a string.
+pub(crate) fn is_number_or_bool(path: &[&str]) -> bool {
+    // The items of the lists have no key
+    fn item(mut shape: &'static Shape) -> &'static Shape {
+        while let Shape::List(inner) = shape {
+            shape = inner;
+        }
+        shape
+    }
+    let mut shape = &Shape::Fields(CONFIG);
+    for name in path {
+        let fields = match item(shape) {
+            Shape::Fields(fields) => fields,
+            Shape::Entries => ENTRY,
+            Shape::Map(value) => {
+                shape = value;
+                continue;
+            }
+            _ => return false,
+        };
+        match fields.iter().find(|field| field.name == *name) {
+            Some(field) => shape = &field.shape,
+            None => return false,
+        }
+    }
+    matches!(
+        item(shape),
+        Shape::Bool | Shape::Integer | Shape::Submodules
+    )
+}
+
+#[derive(Clone)]
+enum Value {
+    // The value and whether it is plain, i.e. not quoted
+    Scalar(String, bool),
+    Sequence(Vec<Node>),
+    Mapping(Vec<(Node, Node)>),
+}
+
+// A YAML value with the position it starts at
+#[derive(Clone)]
+struct Node {
+    value: Value,
+    line: usize,
+    column: usize,
+}
+
+impl Node {
+    fn scalar(&self) -> Option<&str> {
+        match &self.value {
+            Value::Scalar(value, _) if !self.is_null() => Some(value),
+            _ => None,
+        }
+    }
+
+    fn is_null(&self) -> bool {
+        matches!(&self.value, Value::Scalar(value, true) if ["~", "null", ""].contains(&value.as_str()))
+    }
+
+    fn get(&self, key: &str) -> Option<&Node> {
+        match &self.value {
+            Value::Mapping(pairs) => pairs
+                .iter()
+                .find(|(k, _)| k.scalar() == Some(key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match &self.value {
+            Value::Scalar(..) if self.is_null() => "nothing",
+            Value::Scalar(..) => "a scalar",
+            Value::Sequence(_) => "a list",
+            Value::Mapping(_) => "a mapping",
+        }
+    }
+}
+
+struct Open {
+    mapping: bool,
+    anchor: usize,
+    mark: Marker,
+    children: Vec<Node>,
+}
+
+// Builds the tree of nodes of the first document from the parser events
+#[derive(Default)]
+struct Builder {
+    stack: Vec<Open>,
+    anchors: HashMap<usize, Node>,
+    root: Option<Node>,
+}
+
+impl Builder {
+    fn push(&mut self, node: Node, anchor: usize) {
+        if anchor > 0 {
+            self.anchors.insert(anchor, node.clone());
+        }
+        if let Some(open) = self.stack.last_mut() {
+            open.children.push(node);
+        } else if self.root.is_none() {
+            self.root = Some(node);
+        }
+    }
+}
+
+fn node(value: Value, mark: Marker) -> Node {
+    Node {
+        value,
+        line: mark.line(),
+        column: mark.col() + 1,
+    }
+}
+
+impl MarkedEventReceiver for Builder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(value, style, anchor, _) => {
+                let plain = style == TScalarStyle::Plain;
+                self.push(node(Value::Scalar(value, plain), mark), anchor)
+            }
+            Event::Alias(anchor) => {
+                if let Some(aliased) = self.anchors.get(&anchor).cloned() {
+                    self.push(node(aliased.value, mark), 0)
+                }
+            }
+            Event::SequenceStart(anchor) | Event::MappingStart(anchor) => self.stack.push(Open {
+                mapping: matches!(event, Event::MappingStart(_)),
+                anchor,
+                mark,
+                children: vec![],
+            }),
+            Event::SequenceEnd | Event::MappingEnd => {
+                let open = self.stack.pop().unwrap();
+                // A block mapping is marked after its first key
+                let (line, column) = match open.children.first() {
+                    Some(key) if open.mapping => (key.line, key.column),
+                    _ => (open.mark.line(), open.mark.col() + 1),
+                };
+                let value = if open.mapping {
+                    let mut children = open.children.into_iter();
+                    let mut pairs = vec![];
+                    while let (Some(key), Some(value)) = (children.next(), children.next()) {
+                        pairs.push((key, value));
+                    }
+                    Value::Mapping(pairs)
+                } else {
+                    Value::Sequence(open.children)
+                };
+                let node = Node {
+                    value,
+                    line,
+                    column,
+                };
+                self.push(node, open.anchor)
+            }
+            _ => (),
+        }
+    }
+}
+
+// The closest candidate to a misspelled word, if close enough
+pub(crate) fn suggest<'a>(
+    word: &str,
+    candidates: impl Iterator<Item = &'a str>,
+) -> Option<&'a str> {
+    candidates
+        .map(|candidate| {
+            let distance = strsim::osa_distance(&word.to_lowercase(), &candidate.to_lowercase());
+            (distance, candidate)
+        })
+        .filter(|(distance, candidate)| *distance <= std::cmp::max(1, candidate.len() / 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+#[derive(Default)]
+struct Validator {
+    problems: Vec<Problem>,
+    // The file being checked, empty if it is not read from one
+    file: String,
+    // The folder each entry is retrieved into, with the file and line of the
+    // entry
+    folders: HashMap<String, (String, usize)>,
+    // The configuration includes others, which can define what it lacks
+    includes: bool,
+    profiles: Vec<String>,
+}
+
+impl Validator {
+    fn report(&mut self, node: &Node, severity: Severity, message: String) {
+        self.problems.push(Problem {
+            line: node.line,
+            column: node.column,
+            severity,
+            message,
+        });
+    }
+
+    fn check(&mut self, node: &Node, shape: &Shape, what: &str) {
+        // Only known once the variables are substituted
+        if node.scalar().is_some_and(|value| value.contains("${")) {
+            return;
+        }
+        let valid = match shape {
+            Shape::Bool => matches!(node.scalar(), Some("true" | "false")),
+            Shape::Integer => node.scalar().is_some_and(|s| s.parse::<u64>().is_ok()),
+            Shape::Text => node.scalar().is_some(),
+            Shape::OneOf(values) => match node.scalar() {
+                Some(value) if values.contains(&value) => true,
+                Some(value) => {
+                    let hint = match suggest(value, values.iter().copied()) {
+                        Some(suggestion) => format!(", did you mean `{}`?", suggestion),
+                        None => String::new(),
+                    };
+                    let message = format!(
+                        "invalid {} `{}`, expected one of {}{}",
+                        what,
+                        value,
+                        values.join(", "),
+                        hint
+                    );
+                    self.report(node, Severity::Error, message);
+                    return;
+                }
+                None => false,
+            },
+            Shape::Submodules => matches!(node.scalar(), Some("true" | "false" | "recursive")),
+            Shape::List(item) => match &node.value {
+                Value::Sequence(items) => {
+                    for item_node in items {
+                        self.check(item_node, item, what);
+                    }
+                    true
+                }
+                _ => false,
+            },
+            Shape::Map(value) => match &node.value {
+                Value::Mapping(pairs) => {
+                    for (_, value_node) in pairs {
+                        self.check(value_node, value, what);
+                    }
+                    true
+                }
+                _ => false,
+            },
+            Shape::Fields(fields) => match &node.value {
+                Value::Mapping(_) => {
+                    self.check_fields(node, fields, &[], what);
+                    true
+                }
+                _ => false,
+            },
+            Shape::Entries => match &node.value {
+                Value::Sequence(entries) => {
+                    for entry in entries {
+                        self.check_entry(entry);
+                    }
+                    true
+                }
+                _ => false,
+            },
+        };
+        if !valid {
+            let expected = match shape {
+                Shape::Bool => "true or false",
+                Shape::Integer => "a whole number",
+                Shape::Text => "a string",
+                Shape::OneOf(_) => "a string",
+                Shape::Submodules => "true, false or recursive",
+                Shape::List(_) | Shape::Entries => "a list",
+                Shape::Map(_) | Shape::Fields(_) => "a mapping",
+            };
+            let message = format!("invalid {}, expected {}", what, expected);
+            self.report(node, Severity::Error, message);
+        }
+    }
+
+    // Check the keys of a mapping, `others` being keys valid in the
+    // alternative layouts of the mapping, which are not suggested
+    fn check_fields(&mut self, node: &Node, fields: &[Field], others: &[&Field], what: &str) {
+        let pairs = match &node.value {
+            Value::Mapping(pairs) => pairs,
+            _ => return,
+        };
+        let mut seen = vec![];
+        for (key, value) in pairs {
+            let name = key.scalar().unwrap_or_default();
+            if seen.contains(&name) {
+                self.report(key, Severity::Error, format!("duplicate key `{}`", name));
+                continue;
+            }
+            seen.push(name);
+            // Points editors to the JSON Schema of the configuration
+            if name == "$schema" && std::ptr::eq(fields, CONFIG) {
+                continue;
+            }
+            match fields.iter().find(|field| field.name == name) {
+                Some(field) if field.required || !value.is_null() => {
+                    self.check(value, &field.shape, &format!("`{}`", name))
+                }
+                Some(_) => (),
+                None if others.iter().any(|field| field.name == name) => {
+                    let message = format!("`{}` is not valid in {}", name, what);
+                    self.report(key, Severity::Error, message)
+                }
+                None => {
+                    let hint = match suggest(name, fields.iter().map(|field| field.name)) {
+                        Some(suggestion) => format!(", did you mean `{}`?", suggestion),
+                        None => String::new(),
+                    };
+                    let message = format!("unknown key `{}` in {}{}", name, what, hint);
+                    self.report(key, Severity::Warning, message)
+                }
+            }
+        }
+        // The included files can provide the required fields of the configuration
+        let included = self.includes && std::ptr::eq(fields, CONFIG);
+        for field in fields.iter().filter(|field| field.required && !included) {
+            if !seen.contains(&field.name) {
+                let message = format!("missing `{}` in {}", field.name, what);
+                self.report(node, Severity::Error, message);
+            }
+        }
+    }
+
+    fn check_entry(&mut self, entry: &Node) {
+        if !matches!(entry.value, Value::Mapping(_)) {
+            let message = format!("invalid entry, expected a mapping, found {}", entry.kind());
+            self.report(entry, Severity::Error, message);
+            return;
+        }
+        let (url, path) = (entry.get("url"), entry.get("path"));
+        let folder = match (url.and_then(Node::scalar), path.and_then(Node::scalar)) {
+            (Some(_), Some(_)) => {
+                let message = String::from("an entry has either a `url` or a `path`, not both");
+                self.report(entry, Severity::Error, message);
+                return;
+            }
+            (Some(url), None) => {
+                let others = FOLDER.iter().collect::<Vec<_>>();
+                self.check_fields(entry, REPO, &others, "a repository");
+                repo_folder(url)
+            }
+            (None, Some(path)) => {
+                let others = REPO.iter().collect::<Vec<_>>();
+                self.check_fields(entry, FOLDER, &others, "a folder");
+                local_folder(path)
+            }
+            (None, None) => {
+                self.check_fields(entry, ENTRY, &[], "an entry");
+                let message = String::from("missing `url` or `path` in an entry");
+                self.report(entry, Severity::Error, message);
+                return;
+            }
+        };
+        match (
+            entry.get("build"),
+            entry.get("profile").and_then(Node::scalar),
+        ) {
+            (None, None) => {
+                let message = String::from("missing `build` or `profile` in an entry");
+                self.report(entry, Severity::Error, message);
+            }
+            (Some(build), None) => {
+                if matches!(&build.value, Value::Sequence(commands) if commands.is_empty()) {
+                    let message = format!("empty `build` list, `{}` is only packaged", folder);
+                    self.report(build, Severity::Warning, message);
+                }
+            }
+            (_, Some(profile)) => {
+                let known = self.profiles.iter().any(|name| name == profile);
+                if !known && !self.includes && !profile.contains("${") {
+                    let hint = match suggest(profile, self.profiles.iter().map(String::as_str)) {
+                        Some(suggestion) => format!(", did you mean `{}`?", suggestion),
+                        None => String::new(),
+                    };
+                    let message = format!("unknown build profile `{}`{}", profile, hint);
+                    let node = entry.get("profile").unwrap();
+                    self.report(node, Severity::Error, message);
+                }
+            }
+        }
+        // Only known once the variables are substituted
+        if folder.contains("${") {
+            return;
+        }
+        match self.folders.get(folder) {
+            Some((file, line)) => {
+                let message = match file == &self.file {
+                    true => format!(
+                        "`{}` is already retrieved by the entry at line {}",
+                        folder, line
+                    ),
+                    false => format!(
+                        "`{}` is already retrieved by the entry at {}:{}",
+                        folder, file, line
+                    ),
+                };
+                self.report(entry, Severity::Error, message)
+            }
+            None => {
+                self.folders
+                    .insert(folder.to_string(), (self.file.clone(), entry.line));
+            }
+        }
+    }
+}
+
+fn yaml_tree(config: &str) -> std::result::Result<Option<Node>, Problem> {
+    let mut builder = Builder::default();
+    if let Err(error) = Parser::new(config.chars()).load(&mut builder, false) {
+        let message = error.to_string();
+        // The position is reported separately
+        let message = message.split(" at line ").next().unwrap_or_default();
+        return Err(Problem {
+            line: error.marker().line(),
+            column: error.marker().col() + 1,
+            severity: Severity::Error,
+            message: message.to_string(),
+        });
+    }
+    Ok(builder.root)
+}
+
+// The line and column of the start of a span of a TOML document
+fn toml_position(config: &str, span: Option<Range<usize>>) -> Option<(usize, usize)> {
+    let before = &config[..span?.start];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+    Some((line, column))
+}
+
+fn toml_mapping<'a>(
+    config: &str,
+    pairs: impl Iterator<Item = (&'a toml_edit::Key, &'a Item)>,
+) -> Value {
+    Value::Mapping(
+        pairs
+            .map(|(key, item)| {
+                let (line, column) = toml_position(config, key.span()).unwrap_or((1, 1));
+                let name = Node {
+                    value: Value::Scalar(key.get().to_string(), false),
+                    line,
+                    column,
+                };
+                (name, toml_item(config, item, (line, column)))
+            })
+            .collect(),
+    )
+}
+
+// `at` is the position of the key of the item, for the implicit tables
+fn toml_item(config: &str, item: &Item, at: (usize, usize)) -> Node {
+    let (line, column) = toml_position(config, item.span()).unwrap_or(at);
+    let value = match item {
+        Item::None => Value::Scalar(String::new(), true),
+        Item::Value(value) => return toml_value(config, value, at),
+        Item::Table(table) => toml_mapping(
+            config,
+            table.iter().filter_map(|(key, _)| table.get_key_value(key)),
+        ),
+        Item::ArrayOfTables(tables) => Value::Sequence(
+            tables
+                .iter()
+                .map(|table| {
+                    let (line, column) = toml_position(config, table.span()).unwrap_or(at);
+                    let pairs = table.iter().filter_map(|(key, _)| table.get_key_value(key));
+                    Node {
+                        value: toml_mapping(config, pairs),
+                        line,
+                        column,
+                    }
+                })
+                .collect(),
+        ),
+    };
+    Node {
+        value,
+        line,
+        column,
+    }
+}
+
+fn toml_value(config: &str, value: &toml_edit::Value, at: (usize, usize)) -> Node {
+    use toml_edit::Value as Toml;
+    let (line, column) = toml_position(config, value.span()).unwrap_or(at);
+    let value = match value {
+        Toml::String(text) => Value::Scalar(text.value().clone(), false),
+        Toml::Integer(number) => Value::Scalar(number.value().to_string(), true),
+        Toml::Float(number) => Value::Scalar(number.value().to_string(), true),
+        Toml::Boolean(flag) => Value::Scalar(flag.value().to_string(), true),
+        Toml::Datetime(datetime) => Value::Scalar(datetime.value().to_string(), false),
+        Toml::Array(items) => Value::Sequence(
+            items
+                .iter()
+                .map(|item| toml_value(config, item, (line, column)))
+                .collect(),
+        ),
+        Toml::InlineTable(table) => toml_mapping(
+            config,
+            table.iter().filter_map(|(key, _)| table.get_key_value(key)),
+        ),
+    };
+    Node {
+        value,
+        line,
+        column,
+    }
+}
+
+fn toml_tree(config: &str) -> std::result::Result<Option<Node>, Problem> {
+    match ImDocument::parse(config) {
+        Ok(document) => Ok(Some(Node {
+            value: toml_mapping(
+                config,
+                document
+                    .iter()
+                    .filter_map(|(key, _)| document.get_key_value(key)),
+            ),
+            line: 1,
+            column: 1,
+        })),
+        Err(error) => {
+            let (line, column) = toml_position(config, error.span()).unwrap_or((1, 1));
+            Err(Problem {
+                line,
+                column,
+                severity: Severity::Error,
+                message: error.message().trim_end().replace('\n', ", "),
+            })
+        }
+    }
+}
+
+// Check a document, returning its root. An included document can leave out
+// what the others define.
+fn check_document(
+    validator: &mut Validator,
+    config: &str,
+    format: Format,
+    included: bool,
+) -> Option<Node> {
+    let root = match format {
+        Format::Yaml => yaml_tree(config),
+        // JSON is YAML, but for the tabs it can be indented with
+        Format::Json => yaml_tree(&config.replace('\t', " ")),
+        Format::Toml => toml_tree(config),
+    };
+    let root = match root {
+        Ok(root) => root,
+        Err(problem) => {
+            validator.problems.push(problem);
+            return None;
+        }
+    };
+    match &root {
+        Some(root) if matches!(root.value, Value::Mapping(_)) => {
+            validator.includes = included || root.get("include").is_some();
+            validator.profiles = match root.get("profiles").map(|node| &node.value) {
+                Some(Value::Mapping(profiles)) => profiles
+                    .iter()
+                    .filter_map(|(name, _)| name.scalar().map(String::from))
+                    .collect(),
+                _ => vec![],
+            };
+            validator.check_fields(root, CONFIG, &[], "the configuration")
+        }
+        root => validator.problems.push(Problem {
+            line: root.as_ref().map_or(1, |root| root.line),
+            column: root.as_ref().map_or(1, |root| root.column),
+            severity: Severity::Error,
+            message: String::from("the configuration must be a mapping"),
+        }),
+    }
+    root
+}
+
+// The problems found so far, in the order they appear in the file
+fn take_problems(validator: &mut Validator) -> Vec<Problem> {
+    let mut problems = std::mem::take(&mut validator.problems);
+    problems.sort_by_key(|problem| (problem.line, problem.column));
+    problems
+}
+
+/// Check a configuration, returning its problems in the order they appear
+/// in the file.
+pub fn validate(config: &str, format: Format) -> Vec<Problem> {
+    let mut validator = Validator::default();
+    check_document(&mut validator, config, format, false);
+    take_problems(&mut validator)
+}
+
+// Check the file and the files it includes, `chain` holding the files being
+// included
+fn check_file(
+    validator: &mut Validator,
+    path: &Path,
+    chain: &mut Vec<PathBuf>,
+    problems: &mut Vec<(PathBuf, Problem)>,
+) -> Result<()> {
+    let config = fs::read_to_string(path)
+        .with_context(|| format!("could not read file `{}`", path.to_string_lossy()))?;
+    validator.file = path.to_string_lossy().to_string();
+    let root = check_document(validator, &config, Format::from_path(path), chain.len() > 1);
+    let includes: &[Node] = match root.as_ref().and_then(|root| root.get("include")) {
+        Some(Node {
+            value: Value::Sequence(includes),
+            ..
+        }) => includes,
+        _ => &[],
+    };
+    let mut found = vec![];
+    for include in includes {
+        let included = match include.scalar() {
+            Some(included) => path.parent().unwrap_or(Path::new(".")).join(included),
+            None => continue,
+        };
+        match included.canonicalize() {
+            // Reported when the configuration is loaded
+            Ok(canonical) if chain.contains(&canonical) => (),
+            Ok(canonical) => found.push((included, canonical)),
+            Err(_) => {
+                let message = format!("could not read file `{}`", included.to_string_lossy());
+                validator.report(include, Severity::Error, message);
+            }
+        }
+    }
+    problems.extend(
+        take_problems(validator)
+            .into_iter()
+            .map(|problem| (path.to_path_buf(), problem)),
+    );
+    for (included, canonical) in found {
+        chain.push(canonical);
+        check_file(validator, &included, chain, problems)?;
+        chain.pop();
+    }
+    Ok(())
+}
+
+/// Check a configuration file and the files it includes, returning the
+/// problems of each file in the order they appear in it. The entries of all
+/// the files must be retrieved into distinct folders.
+pub fn validate_file(path: &Path) -> Result<Vec<(PathBuf, Problem)>> {
+    let mut problems = vec![];
+    let canonical = path
+        .canonicalize()
+        .with_context(|| format!("could not read file `{}`", path.to_string_lossy()))?;
+    check_file(
+        &mut Validator::default(),
+        path,
+        &mut vec![canonical],
+        &mut problems,
+    )?;
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let problems = validate(
+            r#"
+repositories:
+  - url: http://mygit.com/user/project-A
+    brnach: master
+    build: []
+  - path: ../project-B
+    build:
+      - npm run build
+    lfs: true
+  - url: http://mygit.com/other/project-A.git
+    branch: main
+    build: [npm test]
+    ecosystem: nmp
+version_check: eror
+timeout:
+  build: 60
+"#,
+            Format::Yaml,
+        )
+        .iter()
+        .map(|problem| problem.to_string())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            vec![
+                "3:5: error: missing `branch` in a repository",
+                "4:5: warning: unknown key `brnach` in a repository, did you mean `branch`?",
+                "5:12: warning: empty `build` list, `project-A` is only packaged",
+                "9:5: error: `lfs` is not valid in a folder",
+                "10:5: error: `project-A` is already retrieved by the entry at line 3",
+                "13:16: error: invalid `ecosystem` `nmp`, expected one of npm, cargo, python, did you mean `npm`?",
+                "14:16: error: invalid `version_check` `eror`, expected one of ignore, warn, error, did you mean `error`?",
+                "15:1: warning: unknown key `timeout` in the configuration, did you mean `timeouts`?",
+            ]
+        );
+
+        let problems = validate(
+            r#"
+profiles:
+  web: [npm run build]
+repositories:
+  - path: ${root}/project-A
+    profile: wbe
+    ecosystem: ${ecosystem}
+  - path: ../project-B
+"#,
+            Format::Yaml,
+        )
+        .iter()
+        .map(|problem| problem.to_string())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            vec![
+                "6:14: error: unknown build profile `wbe`, did you mean `web`?",
+                "8:5: error: missing `build` or `profile` in an entry",
+            ]
+        );
+
+        let problems = validate("repositories:\n  - url: [\n", Format::Yaml);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].severity, Severity::Error);
+        assert_eq!(problems[0].line, 3);
+    }
+
+    #[test]
+    fn test_fields_match_schema() {
+        let schema: serde_json::Value =
+            serde_json::from_str(&crate::config::json_schema()).unwrap();
+        let tables = [
+            (CONFIG, &schema),
+            (REPO, &schema["definitions"]["Repo"]),
+            (FOLDER, &schema["definitions"]["Folder"]),
+            (RETRIES, &schema["definitions"]["Retries"]),
+            (RETRY_POLICY, &schema["definitions"]["RetryPolicy"]),
+            (TIMEOUTS, &schema["definitions"]["Timeouts"]),
+            (PUBLISH, &schema["definitions"]["Publish"]),
+        ];
+        for (fields, definition) in tables {
+            let mut names = fields.iter().map(|field| field.name).collect::<Vec<_>>();
+            let mut properties = definition["properties"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            names.sort_unstable();
+            properties.sort_unstable();
+            assert_eq!(names, properties);
+            for field in fields.iter() {
+                let required = definition["required"]
+                    .as_array()
+                    .is_some_and(|required| required.contains(&field.name.into()));
+                assert_eq!(field.required, required, "{}", field.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate_formats() {
+        let problems = validate(
+            r#"
+stamp = "yes"
+
+[[repositories]]
+url = "http://mygit.com/user/project-A"
+brnach = "master"
+build = []
+
+[retry.clone]
+atempts = 3
+"#,
+            Format::Toml,
+        )
+        .iter()
+        .map(|problem| problem.to_string())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            vec![
+                "2:9: error: invalid `stamp`, expected true or false",
+                "4:1: error: missing `branch` in a repository",
+                "6:1: warning: unknown key `brnach` in a repository, did you mean `branch`?",
+                "7:9: warning: empty `build` list, `project-A` is only packaged",
+                "10:1: warning: unknown key `atempts` in `clone`, did you mean `attempts`?",
+            ]
+        );
+
+        let problems = validate(
+            "{\n\t\"$schema\": \"integ.schema.json\",\n\t\"repositories\": [\n\t\t\
+             {\"path\": \"/x\", \"build\": [\"a\"], \"ecosystem\": \"nmp\"}\n\t]\n}\n",
+            Format::Json,
+        )
+        .iter()
+        .map(|problem| problem.to_string())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            vec!["4:47: error: invalid `ecosystem` `nmp`, expected one of npm, cargo, python, did you mean `npm`?"]
+        );
+    }
+
+    #[test]
+    fn test_validate_file() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("common")).unwrap();
+        fs::write(
+            root.path().join("common").join("base.toml"),
+            r#"
+[[repositories]]
+url = "http://mygit.com/user/project-A"
+branch = "master"
+profile = "web"
+lfs = "yes"
+"#,
+        )
+        .unwrap();
+        let config = root.path().join("integ.yaml");
+        fs::write(
+            &config,
+            r#"
+include: [common/base.toml, missing.yaml]
+repositories:
+  - path: ../project-A
+    build: [npm test]
+"#,
+        )
+        .unwrap();
+        let base = root.path().join("common").join("base.toml");
+        let problems = validate_file(&config)
+            .unwrap()
+            .iter()
+            .map(|(file, problem)| format!("{}:{}", file.to_string_lossy(), problem))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            vec![
+                format!(
+                    "{}:2:29: error: could not read file `{}`",
+                    config.to_string_lossy(),
+                    root.path().join("missing.yaml").to_string_lossy()
+                ),
+                format!(
+                    "{}:2:1: error: `project-A` is already retrieved by the entry at {}:4",
+                    base.to_string_lossy(),
+                    config.to_string_lossy()
+                ),
+                format!(
+                    "{}:6:7: error: invalid `lfs`, expected true or false",
+                    base.to_string_lossy()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Checks of the versions of the integrated packages against the ranges
+//! declared by their dependents.
+use crate::config::{DependencyKind, Entry};
+use crate::ecosystem::{ecosystem, Manifest};
+use std::collections::HashMap;
+use std::fmt;
+
+/// An integrated package whose version is out of the range declared by one
+/// of its dependents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub dependent: String,
+    pub dependency: String,
+    pub requirement: String,
+    pub version: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} requires {} {} but {} is integrated",
+            self.dependent, self.dependency, self.requirement, self.version
+        )
+    }
+}
+
+/// Compare the version of each integrated package with the ranges its
+/// dependents declare through the given kinds of dependencies. Returns the
+/// mismatches, then the requirements which are not understood by the
+/// ecosystem of the dependent and could not be checked.
+pub(crate) fn check_versions(
+    entries: &[Entry],
+    manifests: &[Manifest],
+    kinds: &[DependencyKind],
+) -> (Vec<Mismatch>, Vec<Mismatch>) {
+    let versions = manifests
+        .iter()
+        .filter_map(|manifest| Some((manifest.name.as_str(), manifest.version.as_deref()?)))
+        .collect::<HashMap<&str, &str>>();
+    let mut mismatches = vec![];
+    let mut unchecked = vec![];
+    for (entry, manifest) in entries.iter().zip(manifests) {
+        let ecosystem = ecosystem(entry.ecosystem());
+        for dependency in &manifest.dependencies {
+            if !kinds.contains(&dependency.kind) {
+                continue;
+            }
+            let version = match versions.get(dependency.name.as_str()) {
+                Some(version) => version,
+                None => continue,
+            };
+            let found = match ecosystem.satisfies(version, &dependency.requirement) {
+                Some(true) => continue,
+                Some(false) => &mut mismatches,
+                None => &mut unchecked,
+            };
+            let mismatch = Mismatch {
+                dependent: manifest.name.clone(),
+                dependency: dependency.name.clone(),
+                requirement: dependency.requirement.clone(),
+                version: version.to_string(),
+            };
+            // The same range is often declared as a peer and a dev dependency
+            if !found.contains(&mismatch) {
+                found.push(mismatch);
+            }
+        }
+    }
+    (mismatches, unchecked)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::load_config;
+    use crate::ecosystem::Dependency;
+
+    fn manifest(name: &str, version: &str, dependencies: &[(&str, &str)]) -> Manifest {
+        Manifest {
+            name: String::from(name),
+            version: Some(String::from(version)),
+            dependencies: dependencies
+                .iter()
+                .map(|(name, requirement)| Dependency {
+                    name: String::from(*name),
+                    requirement: String::from(*requirement),
+                    kind: DependencyKind::Normal,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_check_versions() {
+        let config = load_config(
+            r#"
+            repositories:
+                - { path: /a, build: [] }
+                - { path: /b, build: [] }
+                - { path: /c, build: [] }
+                - { path: /d, build: [] }
+            "#,
+        )
+        .unwrap();
+        let manifests = vec![
+            manifest("@x/a", "1.4.0", &[("lodash", "^4.0.0")]),
+            manifest("@x/b", "3.0.0", &[("@x/a", "^1.2.0")]),
+            manifest("@x/c", "1.0.0", &[("@x/a", "~1.3.0"), ("@x/b", "^2.0.0")]),
+            manifest("@x/d", "1.0.0", &[("@x/a", "file:../a")]),
+        ];
+        let (mismatches, unchecked) =
+            check_versions(&config.repositories, &manifests, &config.edges);
+        assert_eq!(
+            mismatches
+                .iter()
+                .map(|mismatch| mismatch.to_string())
+                .collect::<Vec<String>>(),
+            vec![
+                "@x/c requires @x/a ~1.3.0 but 1.4.0 is integrated",
+                "@x/c requires @x/b ^2.0.0 but 3.0.0 is integrated",
+            ]
+        );
+        assert_eq!(unchecked.len(), 1);
+        assert_eq!(unchecked[0].requirement, "file:../a");
+        assert_eq!(
+            check_versions(&config.repositories, &manifests, &[DependencyKind::Dev]),
+            (vec![], vec![])
+        );
+    }
+}